@@ -0,0 +1,180 @@
+//! Throughput of the engine: parsing rows, applying each type of row,
+//! whole runs of generated inputs and writing the accounts.
+//!
+//! ```text
+//! cargo bench --bench engine -- --save-baseline main
+//! BENCH_ROWS=1000000,10000000 cargo bench --bench engine -- --baseline main
+//! ```
+//!
+//! `BENCH_ROWS` is a comma separated list of input sizes, 1000000 by default.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use csv::{ByteRecord, ReaderBuilder, StringRecord, Trim, Writer};
+use std::env;
+use std::error::Error;
+use std::fmt::Write;
+use std::hint::black_box;
+use std::io;
+use std::time::Duration;
+
+use kraken_homework::generator::{output_rows, Generator, GeneratorConfig};
+use kraken_homework::ingest::{parse_row, TypeAliases};
+use kraken_homework::profile::Profile;
+use kraken_homework::proccess_input_output::{output_client_data, proccess_bytes, proccess_input};
+use kraken_homework::transactions::{chargeback, deposit, dispute, resolve, withdrawal, Outcome, TransactionTemplate};
+use kraken_homework::transactions_info::TransactionsInfo;
+
+const CLIENTS: u32 = 1000;
+
+fn sizes() -> Vec<u32> {
+    env::var("BENCH_ROWS")
+        .map(|sizes| sizes.split(',').map(|size| size.trim().parse().expect("BENCH_ROWS must be row counts.")).collect())
+        .unwrap_or_else(|_| vec![1_000_000])
+}
+
+/// `rows` rows of `tx_type` from tx id `first`, spread over the clients.
+fn rows(tx_type: &str, first: u32, rows: u32, amount: &str) -> String {
+    let mut csv = String::from("type, client, tx, amount\n");
+    for tx in first..first + rows {
+        writeln!(csv, "{}, {}, {}, {}", tx_type, tx % CLIENTS + 1, tx, amount).unwrap();
+    }
+    csv
+}
+
+fn templates(csv: &str) -> Vec<TransactionTemplate> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(csv.as_bytes());
+    let headers = rdr.headers().unwrap().clone();
+    let mut record = StringRecord::new();
+    let mut templates = Vec::new();
+    while rdr.read_record(&mut record).unwrap() {
+        templates.push(record.deserialize(Some(&headers)).unwrap());
+    }
+    templates
+}
+
+/// Rows read with `parse_row`, as `proccess_input` does for four-column files.
+fn fast_templates(csv: &str) -> Vec<TransactionTemplate> {
+    let mut rdr = ReaderBuilder::new().from_reader(csv.as_bytes());
+    let mut record = ByteRecord::new();
+    let mut templates = Vec::new();
+    let aliases = TypeAliases::default();
+    while rdr.read_byte_record(&mut record).unwrap() {
+        templates.push(parse_row(&record, record.position().map_or(0, |position| position.line()), &aliases).unwrap());
+    }
+    templates
+}
+
+fn run_into(csv: &str, transactions_info: &mut TransactionsInfo) {
+    proccess_input(&mut ReaderBuilder::new().flexible(true).from_reader(csv.as_bytes()), transactions_info).unwrap();
+}
+
+fn run(csv: &str) -> TransactionsInfo {
+    let mut transactions_info = TransactionsInfo::new();
+    run_into(csv, &mut transactions_info);
+    transactions_info
+}
+
+fn generated(rows: u32, dispute_rate: f64) -> String {
+    let config = GeneratorConfig { clients: CLIENTS as u16, rows, dispute_rate, seed: 1, ..GeneratorConfig::default() };
+    let mut buffer = Vec::new();
+    output_rows(&mut Writer::from_writer(&mut buffer), &mut Generator::new(config)).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for size in sizes() {
+        let csv = generated(size, 0.01);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("serde", size), &csv, |b, csv| b.iter(|| templates(black_box(csv))));
+        group.bench_with_input(BenchmarkId::new("fast", size), &csv, |b, csv| b.iter(|| fast_templates(black_box(csv))));
+    }
+    group.finish();
+}
+
+type Apply = fn(TransactionTemplate, &mut TransactionsInfo) -> Result<Outcome, Box<dyn Error>>;
+
+/// Cost of one type of row on its own: the rows before it are applied in
+/// the setup and the rows are parsed beforehand.
+fn apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply");
+    for size in sizes() {
+        let deposits = rows("deposit", 1, size, "10.5");
+        let disputes = rows("dispute", 1, size, "");
+        let resolves = rows("resolve", 1, size, "");
+        let steps: [(&str, Apply, Vec<&str>, String); 5] = [
+            ("deposit", deposit, vec![], deposits.clone()),
+            ("withdrawal", withdrawal, vec![&deposits], rows("withdrawal", size + 1, size, "1.25")),
+            ("dispute", dispute, vec![&deposits], disputes.clone()),
+            ("resolve", resolve, vec![&deposits, &disputes], resolves.clone()),
+            ("chargeback", chargeback, vec![&deposits, &disputes, &resolves], rows("chargeback", 1, size, "")),
+        ];
+        group.throughput(Throughput::Elements(size as u64));
+        for (name, apply, before, csv) in steps.iter() {
+            let rows = templates(csv);
+            group.bench_function(BenchmarkId::new(*name, size), |b| {
+                b.iter_batched(
+                    || {
+                        let mut transactions_info = TransactionsInfo::new();
+                        for csv in before.iter() {
+                            run_into(csv, &mut transactions_info);
+                        }
+                        (transactions_info, rows.clone())
+                    },
+                    |(mut transactions_info, rows)| {
+                        for row in rows {
+                            apply(row, &mut transactions_info).unwrap();
+                        }
+                        transactions_info
+                    },
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Whole runs of generated inputs, from bytes to accounts, with 1% and 30%
+/// of dispute rows, with the rows parsed in parallel ('parallel') and with
+/// the rows read by serde ('serde'), through a column that is not in the
+/// rows.
+fn end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("end_to_end");
+    for size in sizes() {
+        group.throughput(Throughput::Elements(size as u64));
+        for (name, dispute_rate) in [("mixed", 0.01), ("dispute_heavy", 0.3)] {
+            let csv = generated(size, dispute_rate);
+            group.bench_with_input(BenchmarkId::new(name, size), &csv, |b, csv| b.iter(|| run(black_box(csv))));
+        }
+        let csv = generated(size, 0.01);
+        group.bench_with_input(BenchmarkId::new("parallel", size), &csv, |b, csv| {
+            b.iter(|| proccess_bytes(black_box(csv.as_bytes()), &Profile::default(), &mut TransactionsInfo::new()).unwrap())
+        });
+        let csv = csv.replacen("type,client,tx,amount", "type,client,tx,amount,timestamp", 1);
+        group.bench_with_input(BenchmarkId::new("serde", size), &csv, |b, csv| b.iter(|| run(black_box(csv))));
+    }
+    group.finish();
+}
+
+/// Writing the accounts of every client id.
+fn output(c: &mut Criterion) {
+    let mut group = c.benchmark_group("output");
+    let clients = u16::MAX as u32;
+    let mut transactions_info = run(&(1..=clients).fold(String::from("type, client, tx, amount\n"), |mut csv, tx| {
+        writeln!(csv, "deposit, {}, {}, 10.5", tx, tx).unwrap();
+        csv
+    }));
+    group.throughput(Throughput::Elements(clients as u64));
+    group.bench_function(BenchmarkId::new("accounts", clients), |b| {
+        b.iter(|| output_client_data(&mut Writer::from_writer(io::sink()), &mut transactions_info).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10).measurement_time(Duration::from_secs(10));
+    targets = parse, apply, end_to_end, output
+}
+criterion_main!(benches);
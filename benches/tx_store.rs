@@ -0,0 +1,153 @@
+//! Memory and throughput of the transaction store against the former
+//! `HashMap<(TxId, ClientID), Transaction>` layout, with dense tx ids and
+//! with tx ids spread over the whole range.
+//!
+//! ```text
+//! cargo bench --bench tx_store -- 20000000
+//! ```
+
+use rust_decimal::Decimal;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::env;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use kraken_homework::transactions::{ClientID, Transaction, TxId};
+use kraken_homework::tx_store::TxStore;
+
+/// Counts the bytes currently allocated.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const DEFAULT_TRANSACTIONS: u32 = 10_000_000;
+
+fn key(id: u32) -> (TxId, ClientID) {
+    (TxId::new(id), ClientID::new((id % u16::MAX as u32) as u16))
+}
+
+fn deposit(id: u32) -> Transaction {
+    Transaction::Deposit { amount: Decimal::new(id as i64, 4), fee: Decimal::ZERO }
+}
+
+/// The engine workload: store every transaction, dispute every tenth one
+/// and look every transaction up once.
+trait Store {
+    fn insert(&mut self, id: u32);
+    fn dispute(&mut self, id: u32);
+    fn lookup(&self, id: u32) -> bool;
+}
+
+#[derive(Default)]
+struct HashMapStore {
+    transactions: HashMap<(TxId, ClientID), Transaction>,
+    disputes: HashMap<(TxId, ClientID), Transaction>,
+}
+
+impl Store for HashMapStore {
+    fn insert(&mut self, id: u32) {
+        self.transactions.insert(key(id), deposit(id));
+    }
+
+    fn dispute(&mut self, id: u32) {
+        self.disputes.insert(key(id), Transaction::Dispute);
+    }
+
+    fn lookup(&self, id: u32) -> bool {
+        let key = key(id);
+        matches!(self.transactions.get(&key), Some(Transaction::Deposit { .. })) && !self.disputes.contains_key(&key)
+    }
+}
+
+impl Store for TxStore {
+    fn insert(&mut self, id: u32) {
+        let (tx, client) = key(id);
+        TxStore::insert(self, &tx, &client, &deposit(id));
+    }
+
+    fn dispute(&mut self, id: u32) {
+        let (tx, client) = key(id);
+        self.set_dispute(&tx, &client, &Transaction::Dispute);
+    }
+
+    fn lookup(&self, id: u32) -> bool {
+        let (tx, client) = key(id);
+        matches!(self.get(&tx, &client), Some(Transaction::Deposit { .. })) && self.get_dispute(&tx, &client).is_none()
+    }
+}
+
+fn rate(operations: u32, elapsed: Duration) -> f64 {
+    operations as f64 / elapsed.as_secs_f64() / 1_000_000.0
+}
+
+/// The `n`th tx id: `n` itself, or `n` scattered over the whole range of
+/// tx ids by a multiplicative hash.
+fn tx_id(n: u32, sparse: bool) -> u32 {
+    match sparse {
+        true => n.wrapping_mul(2_654_435_761),
+        false => n,
+    }
+}
+
+fn run<S: Store>(name: &str, mut store: S, transactions: u32, sparse: bool) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for n in 0..transactions {
+        store.insert(tx_id(n, sparse));
+    }
+    let inserted = start.elapsed();
+    for n in (0..transactions).step_by(10) {
+        store.dispute(tx_id(n, sparse));
+    }
+    let start = Instant::now();
+    let mut found = 0;
+    for n in 0..transactions {
+        found += black_box(store.lookup(tx_id(n, sparse))) as u32;
+    }
+    let looked_up = start.elapsed();
+    let bytes = ALLOCATED.load(Ordering::Relaxed) - before;
+    println!(
+        "{:<8} {:>10.1} MB {:>8.1} bytes/tx {:>8.2} M inserts/s {:>8.2} M lookups/s ({} undisputed)",
+        name,
+        bytes as f64 / 1_000_000.0,
+        bytes as f64 / transactions as f64,
+        rate(transactions, inserted),
+        rate(transactions, looked_up),
+        found,
+    );
+}
+
+fn main() {
+    let transactions = env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_TRANSACTIONS);
+    for (ids, sparse) in [("dense", false), ("sparse", true)] {
+        println!("{} transactions, {} tx ids", transactions, ids);
+        run("HashMap", HashMapStore::default(), transactions, sparse);
+        run("TxStore", TxStore::new(), transactions, sparse);
+    }
+}
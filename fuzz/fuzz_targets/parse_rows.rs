@@ -0,0 +1,19 @@
+#![no_main]
+
+use csv::{ByteRecord, ReaderBuilder};
+use kraken_homework::ingest::{parse_row, TypeAliases};
+use kraken_homework::transactions::TxType;
+use libfuzzer_sys::fuzz_target;
+
+// Reads the bytes as an input file, like `proccess_input` does for files
+// with the columns `type, client, tx, amount`, and parses every row with
+// `parse_row`. Errors are expected, panics are not.
+fuzz_target!(|data: &[u8]| {
+    let aliases = TypeAliases::new(vec![("dep".to_string(), TxType::Deposit), ("cb".to_string(), TxType::Chargeback)]);
+    let mut rdr = ReaderBuilder::new().flexible(true).from_reader(data);
+    let mut record = ByteRecord::new();
+    while let Ok(true) = rdr.read_byte_record(&mut record) {
+        let line = record.position().map_or(0, |position| position.line());
+        let _ = parse_row(&record, line, &aliases);
+    }
+});
@@ -0,0 +1,29 @@
+#![no_main]
+
+use csv::{ReaderBuilder, Trim, Writer};
+use kraken_homework::proccess_input_output::{output_client_data, proccess_partner_input};
+use kraken_homework::profile::Profile;
+use kraken_homework::transactions_info::TransactionsInfo;
+use libfuzzer_sys::fuzz_target;
+
+// Reads the bytes up to the first zero byte as a profiles file, and the
+// rest as an input file of the partner `acme`, which is run through the
+// engine. A profile or a file can be refused with an error, but they must
+// not panic.
+fuzz_target!(|data: &[u8]| {
+    let (profiles, input) = match data.iter().position(|&byte| byte == 0) {
+        Some(end) => (&data[..end], &data[end + 1..]),
+        None => (data, &[][..]),
+    };
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(profiles);
+    let profile = match Profile::load(&mut rdr, "acme") {
+        Ok(profile) => profile,
+        Err(_) => return,
+    };
+    let mut transactions_info = TransactionsInfo::new();
+    let mut rdr = profile.reader_builder().from_reader(input);
+    if proccess_partner_input(&mut rdr, &profile, &mut transactions_info).is_err() {
+        return;
+    }
+    let _ = output_client_data(&mut Writer::from_writer(std::io::sink()), &mut transactions_info);
+});
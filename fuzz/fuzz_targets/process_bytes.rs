@@ -0,0 +1,46 @@
+#![no_main]
+
+use kraken_homework::ingest::{parse_chunks, split_lines, Batch, TypeAliases};
+use kraken_homework::proccess_input_output::{proccess_bytes, proccess_partner_input};
+use kraken_homework::profile::Profile;
+use kraken_homework::transactions_info::TransactionsInfo;
+use libfuzzer_sys::fuzz_target;
+
+// The rows of `batches` up to the first error, and that error.
+fn rows(batches: Vec<Batch>) -> (Vec<String>, Option<String>) {
+    let mut rows = Vec::new();
+    for batch in batches {
+        rows.extend(batch.rows.iter().map(|row| format!("{:?}", row)));
+        if let Some(err) = batch.error {
+            return (rows, Some(err.to_string()));
+        }
+    }
+    (rows, None)
+}
+
+// Runs the bytes through the engine as a file in memory, like `main` does,
+// and row by row, which must give the same accounts and the same error.
+// Files without quotes are also parsed in small pieces, which must give the
+// rows of the whole file.
+fuzz_target!(|data: &[u8]| {
+    let profile = Profile::default();
+    let mut parallel = TransactionsInfo::new();
+    let result = proccess_bytes(data, &profile, &mut parallel).map_err(|err| err.to_string());
+    let mut serial = TransactionsInfo::new();
+    let mut rdr = profile.reader_builder().from_reader(data);
+    let serial_result = proccess_partner_input(&mut rdr, &profile, &mut serial).map_err(|err| err.to_string());
+    assert_eq!(result, serial_result);
+    assert_eq!(parallel.get_clients(), serial.get_clients());
+
+    if data.contains(&b'"') {
+        return;
+    }
+    let aliases = TypeAliases::default();
+    let (whole, lines) = parse_chunks(&[data], 1, b',', &aliases);
+    let whole = rows(whole);
+    for size in [1, 16] {
+        let (pieces, pieces_lines) = parse_chunks(&split_lines(data, size), 1, b',', &aliases);
+        assert_eq!(rows(pieces), whole);
+        assert_eq!(pieces_lines, lines);
+    }
+});
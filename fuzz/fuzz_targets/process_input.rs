@@ -0,0 +1,17 @@
+#![no_main]
+
+use csv::{ReaderBuilder, Trim, Writer};
+use kraken_homework::proccess_input_output::{output_client_data, proccess_input};
+use kraken_homework::transactions_info::TransactionsInfo;
+use libfuzzer_sys::fuzz_target;
+
+// Runs the bytes through the engine as an input file and writes the
+// accounts. A file can be refused with an error, but it must not panic.
+fuzz_target!(|data: &[u8]| {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).flexible(true).from_reader(data);
+    let mut transactions_info = TransactionsInfo::new();
+    if proccess_input(&mut rdr, &mut transactions_info).is_err() {
+        return;
+    }
+    let _ = output_client_data(&mut Writer::from_writer(std::io::sink()), &mut transactions_info);
+});
@@ -0,0 +1,238 @@
+use csv::{Reader, ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::EngineEvent;
+use crate::transactions::{checked_amount, open_account, ClientID, Outcome, Rejection, TxId};
+use crate::transactions_info::TransactionsInfo;
+
+/// A row of the admin input. Admin rows are only read from the separate
+/// admin file, never from the transactions file.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AdminTemplate {
+    #[serde(rename = "type")]
+    pub tx_type: AdminTxType,
+    client: ClientID,
+    tx: TxId,
+    amount: Option<Decimal>,
+    reason: String,
+    operator: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminTxType {
+    Unlock,
+    Close,
+    Adjustment,
+}
+
+impl AdminTxType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminTxType::Unlock => "unlock",
+            AdminTxType::Close => "close",
+            AdminTxType::Adjustment => "adjustment",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AdminTxType> {
+        [AdminTxType::Unlock, AdminTxType::Close, AdminTxType::Adjustment]
+            .into_iter()
+            .find(|tx_type| tx_type.name() == name)
+    }
+}
+
+impl AdminTemplate {
+    pub fn get_client(&self) -> &ClientID {
+        &self.client
+    }
+
+    pub fn get_tx(&self) -> &TxId {
+        &self.tx
+    }
+
+    pub fn get_amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn get_operator(&self) -> &str {
+        &self.operator
+    }
+}
+
+/// An admin row and what the engine did with it.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub line: u64,
+    pub row: AdminTemplate,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditRow<'a> {
+    line: u64,
+    #[serde(rename = "type")]
+    tx_type: &'a AdminTxType,
+    client: &'a ClientID,
+    tx: &'a TxId,
+    amount: Option<Decimal>,
+    reason: &'a str,
+    operator: &'a str,
+    outcome: &'static str,
+    rejection: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn to_row(&self) -> AuditRow<'_> {
+        let (outcome, rejection) = match &self.outcome {
+            Outcome::Applied => ("applied", None),
+            Outcome::Rejected(rejection) => ("rejected", Some(rejection.to_string())),
+        };
+        AuditRow {
+            line: self.line,
+            tx_type: &self.row.tx_type,
+            client: &self.row.client,
+            tx: &self.row.tx,
+            amount: self.row.amount,
+            reason: &self.row.reason,
+            operator: &self.row.operator,
+            outcome,
+            rejection,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
+
+#[derive(Debug, Clone)]
+struct MissingAuthorizationError;
+
+impl fmt::Display for MissingAuthorizationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing reason or operator value")
+    }
+}
+
+impl error::Error for MissingAuthorizationError {}
+
+#[derive(Debug, Clone)]
+struct UnknownOperatorError(String);
+
+impl fmt::Display for UnknownOperatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "operator {} is not allowed", self.0)
+    }
+}
+
+impl error::Error for UnknownOperatorError {}
+
+#[derive(Debug, Deserialize)]
+struct OperatorRow {
+    operator: String,
+}
+
+/// The operators that may write admin rows, loaded from a CSV file with an
+/// `operator` column:
+///
+/// ```text
+/// operator
+/// alice
+/// bob
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Operators {
+    operators: HashSet<String>,
+}
+
+impl Operators {
+    pub fn new<I: IntoIterator<Item = String>>(operators: I) -> Operators {
+        Operators { operators: operators.into_iter().collect() }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Operators> {
+        Operators::load(&mut ReaderBuilder::new().trim(Trim::All).from_path(path)?)
+    }
+
+    pub fn load<R: Read>(rdr: &mut Reader<R>) -> Result<Operators> {
+        let mut operators = HashSet::new();
+        for result in rdr.deserialize() {
+            let row: OperatorRow = result?;
+            operators.insert(row.operator);
+        }
+        Ok(Operators { operators })
+    }
+
+    pub fn contains(&self, operator: &str) -> bool {
+        self.operators.contains(operator)
+    }
+}
+
+/// Checks that the row names a reason code and an operator, and that the
+/// operator is allowed if the operators are set. Without them any operator
+/// is taken as written.
+pub fn authorize(transaction: &AdminTemplate, transactions_info: &TransactionsInfo) -> Result<()> {
+    if transaction.reason.is_empty() || transaction.operator.is_empty() {
+        return Err(Box::new(MissingAuthorizationError));
+    }
+    match transactions_info.get_operators() {
+        Some(operators) if !operators.contains(&transaction.operator) => {
+            Err(Box::new(UnknownOperatorError(transaction.operator.clone())))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn unlock(transaction: &AdminTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    match transactions_info.get_client(&transaction.client) {
+        None => return Ok(Outcome::Rejected(Rejection::UnknownClient)),
+        Some(client) if !client.locked => return Ok(Outcome::Rejected(Rejection::NotLocked)),
+        _ => (),
+    };
+    transactions_info.emit(EngineEvent::Unlocked { client: transaction.client.clone() })?;
+    Ok(Outcome::Applied)
+}
+
+/// Closes an account that has no funds left. Closed accounts reject every
+/// later deposit, withdrawal and transfer.
+pub fn close(transaction: &AdminTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    match transactions_info.get_client(&transaction.client) {
+        None => return Ok(Outcome::Rejected(Rejection::UnknownClient)),
+        Some(client) if !client.total.is_zero() || !client.held.is_zero() => {
+            return Ok(Outcome::Rejected(Rejection::NonZeroBalance))
+        }
+        _ => (),
+    };
+    if transactions_info.is_closed(&transaction.client) {
+        return Ok(Outcome::Rejected(Rejection::AccountClosed));
+    }
+    transactions_info.emit(EngineEvent::Closed { client: transaction.client.clone() })?;
+    Ok(Outcome::Applied)
+}
+
+/// Books a manual correction of `amount` (negative to take funds away) to
+/// the client's available funds.
+pub fn adjustment(transaction: &AdminTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let amount = checked_amount(transaction.amount)?;
+    if transactions_info.is_closed(&transaction.client) {
+        return Ok(Outcome::Rejected(Rejection::AccountClosed));
+    }
+    let available = transactions_info.get_client(&transaction.client).map_or(dec!(0), |client| client.available);
+    if available + amount < dec!(0) {
+        return Ok(Outcome::Rejected(Rejection::InsufficientFunds));
+    }
+    open_account(&transaction.client, transactions_info)?;
+    transactions_info.emit(EngineEvent::Adjusted { client: transaction.client.clone(), tx: transaction.tx.clone(), amount })?;
+    Ok(Outcome::Applied)
+}
@@ -0,0 +1,83 @@
+use csv::Writer;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::transactions::{Client, ClientID};
+
+/// A client row of the delta stream, numbered in the order it was written.
+#[derive(Debug, Serialize)]
+struct DeltaRow<'a> {
+    seq: u64,
+    client: &'a ClientID,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Writes a client row whenever the client changed, at most once every
+/// `every` input rows. The last row of every client is its final state, as
+/// written by `output_client_data`.
+pub struct DeltaStream {
+    wtr: Writer<Box<dyn Write>>,
+    every: u64,
+    rows: u64,
+    seq: u64,
+    changed: Vec<ClientID>,
+    changed_set: HashSet<ClientID>,
+}
+
+impl DeltaStream {
+    pub fn new(writer: Box<dyn Write>, every: u64) -> DeltaStream {
+        DeltaStream {
+            wtr: Writer::from_writer(writer),
+            every: every.max(1),
+            rows: 0,
+            seq: 0,
+            changed: Vec::new(),
+            changed_set: HashSet::new(),
+        }
+    }
+
+    pub fn mark(&mut self, client_id: &ClientID) {
+        if self.changed_set.insert(client_id.clone()) {
+            self.changed.push(client_id.clone());
+        }
+    }
+
+    /// Called after every input row.
+    pub fn row_done(&mut self, clients: &HashMap<ClientID, Client>) -> Result<(), Box<dyn Error>> {
+        self.rows += 1;
+        if self.rows.is_multiple_of(self.every) {
+            self.flush(clients)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every client that changed since the last write.
+    pub fn flush(&mut self, clients: &HashMap<ClientID, Client>) -> Result<(), Box<dyn Error>> {
+        for client_id in self.changed.drain(..) {
+            let mut client = match clients.get(&client_id) {
+                Some(client) => client.clone(),
+                None => continue,
+            };
+            client.rescale(4);
+            self.seq += 1;
+            self.wtr.serialize(DeltaRow {
+                seq: self.seq,
+                client: &client.client,
+                available: client.available,
+                held: client.held,
+                total: client.total,
+                locked: client.locked,
+            })?;
+        }
+        self.changed_set.clear();
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use csv::Writer;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::transactions::{ClientID, Transaction, TxId};
+use crate::transactions_info::TransactionsInfo;
+
+/// When a dispute was opened and how much it held. `row` is the position
+/// of the row across every input, see `TransactionsInfo::get_current_row`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisputeRecord {
+    pub line: u64,
+    pub row: u64,
+    pub timestamp: Option<u64>,
+    pub held: Decimal,
+}
+
+/// One open dispute of the disputes report. `held` is what the dispute
+/// holds now: the disputed amount limited to the funds that were available,
+/// or 0 once it is resolved. The age is counted up to the last row.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DisputeLine {
+    pub client: ClientID,
+    pub tx: TxId,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+    pub amount: Decimal,
+    pub held: Decimal,
+    pub state: &'static str,
+    pub opened_line: Option<u64>,
+    pub age_rows: Option<u64>,
+    pub age_seconds: Option<u64>,
+}
+
+fn state_name(dispute: &Transaction) -> &'static str {
+    match dispute {
+        Transaction::Resolve => "resolved",
+        _ => "disputed",
+    }
+}
+
+/// Every dispute that was not charged back, oldest first, for the clients
+/// in `client_ids` (all if empty) and in `state` (`disputed` or `resolved`,
+/// both if `None`).
+pub fn open_disputes(transactions_info: &TransactionsInfo, client_ids: &[ClientID], state: Option<&str>) -> Vec<DisputeLine> {
+    let mut lines: Vec<DisputeLine> = transactions_info
+        .get_tx_store()
+        .iter_disputes()
+        .filter(|(_, _, _, dispute)| **dispute != Transaction::Chargeback)
+        .filter(|(_, client, _, _)| client_ids.is_empty() || client_ids.contains(client))
+        .filter(|(_, _, _, dispute)| state.is_none_or(|state| state == state_name(dispute)))
+        .map(|(tx, client, transaction, dispute)| {
+            let (tx_type, amount) = match transaction {
+                Transaction::Deposit { amount, .. } => ("deposit", amount),
+                Transaction::Withdrawal { amount, .. } => ("withdrawal", amount),
+                Transaction::Transfer { amount, .. } => ("transfer", amount),
+                _ => ("", dec!(0)),
+            };
+            let record = transactions_info.get_dispute_record(&(tx.clone(), client.clone()));
+            let held = match (dispute, record) {
+                (Transaction::Dispute, Some(record)) => record.held,
+                _ => dec!(0),
+            };
+            let age_seconds = match (record.and_then(|record| record.timestamp), transactions_info.get_current_timestamp()) {
+                (Some(opened), Some(now)) => Some(now.saturating_sub(opened)),
+                _ => None,
+            };
+            DisputeLine {
+                client,
+                tx,
+                tx_type,
+                amount,
+                held,
+                state: state_name(dispute),
+                opened_line: record.map(|record| record.line),
+                age_rows: record.map(|record| transactions_info.get_current_row().saturating_sub(record.row)),
+                age_seconds,
+            }
+        })
+        .collect();
+    lines.sort_by_key(|line| line.opened_line);
+    lines
+}
+
+pub fn output_disputes_csv<W: Write>(
+    wtr: &mut Writer<W>,
+    lines: &[DisputeLine],
+) -> Result<(), Box<dyn Error>> {
+    for line in lines {
+        wtr.serialize(line)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+pub fn output_disputes_json<W: Write>(
+    writer: &mut W,
+    lines: &[DisputeLine],
+) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer_pretty(&mut *writer, lines)?;
+    writeln!(writer)?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rules::RuleKind;
+use crate::transactions::{ClientID, TxId};
+
+/// Everything the engine did, in order. The events are the source of truth:
+/// balances, stored transactions and dispute states are projections of
+/// them, see `projection::apply`. `house` is the client that got the fee.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "event")]
+pub enum EngineEvent {
+    /// A row of the input is processed, the events up to the next one are
+    /// what it did. Rows are counted across all inputs, see
+    /// `TransactionsInfo::get_current_row`.
+    RowRead {
+        line: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    AccountOpened { client: ClientID },
+    Deposited {
+        client: ClientID,
+        tx: TxId,
+        amount: Decimal,
+        fee: Decimal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        house: Option<ClientID>,
+    },
+    /// Booked like `Deposited`, but counted apart from deposits.
+    Refunded {
+        client: ClientID,
+        tx: TxId,
+        amount: Decimal,
+        fee: Decimal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        house: Option<ClientID>,
+    },
+    Withdrew {
+        client: ClientID,
+        tx: TxId,
+        amount: Decimal,
+        fee: Decimal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        house: Option<ClientID>,
+    },
+    WithdrawalRejected { client: ClientID, tx: TxId, amount: Decimal, reason: String },
+    Transferred { client: ClientID, tx: TxId, to_client: ClientID, amount: Decimal },
+    Disputed { client: ClientID, tx: TxId, held: Decimal },
+    Resolved { client: ClientID, tx: TxId, released: Decimal },
+    ChargedBack {
+        client: ClientID,
+        tx: TxId,
+        amount: Decimal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        house: Option<ClientID>,
+    },
+    /// `rule` is the fraud rule that locked the client, if any. Only an
+    /// admin unlock lifts the lock of a rule.
+    Locked {
+        client: ClientID,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rule: Option<RuleKind>,
+    },
+    Unlocked { client: ClientID },
+    Closed { client: ClientID },
+    Adjusted { client: ClientID, tx: TxId, amount: Decimal },
+    /// The transaction left the dispute window and is no longer stored.
+    Evicted { client: ClientID, tx: TxId },
+}
+
+/// Receives every `EngineEvent`. An error stops the processing of the input.
+pub trait EngineObserver {
+    fn on_event(&mut self, event: &EngineEvent) -> Result<(), Box<dyn Error>>;
+
+    /// Called once the whole input is processed.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Writes every event as one JSON line.
+pub struct NdjsonEventLog<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonEventLog<W> {
+    pub fn new(writer: W) -> NdjsonEventLog<W> {
+        NdjsonEventLog { writer }
+    }
+}
+
+impl<W: Write> EngineObserver for NdjsonEventLog<W> {
+    fn on_event(&mut self, event: &EngineEvent) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::transactions::{ClientID, TxType};
+
+/// One row of the fee schedule file:
+///
+/// ```text
+/// type, from, flat, percent, min, max
+/// withdrawal, , 0.5, , ,
+/// deposit, 0, , 1.0, 0.1, 5
+/// deposit, 1000, , 0.5, , 10
+/// ```
+///
+/// A row applies to amounts starting at `from` (0 if empty) until the next
+/// row of the same type. The fee is `flat + amount * percent / 100`, limited
+/// by `min` and `max` when they are set.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FeeTier {
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    pub from: Option<Decimal>,
+    pub flat: Option<Decimal>,
+    pub percent: Option<Decimal>,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeTier {
+    fn lower_bound(&self) -> Decimal {
+        self.from.unwrap_or(dec!(0))
+    }
+
+    fn fee(&self, amount: Decimal) -> Decimal {
+        let mut fee = self.flat.unwrap_or(dec!(0));
+        if let Some(percent) = self.percent {
+            fee += amount * percent / dec!(100);
+        }
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        fee.round_dp(4)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    house: ClientID,
+    tiers: HashMap<TxType, Vec<FeeTier>>,
+}
+
+impl FeeSchedule {
+    pub fn new(house: ClientID, tiers: Vec<FeeTier>) -> FeeSchedule {
+        let mut by_type: HashMap<TxType, Vec<FeeTier>> = HashMap::new();
+        for tier in tiers {
+            by_type.entry(tier.tx_type.clone()).or_default().push(tier);
+        }
+        for tiers in by_type.values_mut() {
+            tiers.sort_by_key(|tier| tier.lower_bound());
+        }
+        FeeSchedule { house, tiers: by_type }
+    }
+
+    pub fn from_path(house: ClientID, path: &Path) -> Result<FeeSchedule, Box<dyn Error>> {
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+        let mut tiers = Vec::new();
+        for result in rdr.deserialize() {
+            let tier: FeeTier = result?;
+            if let TxType::Unknown(name) = &tier.tx_type {
+                return Err(format!("unknown type {} in fee tier", name).into());
+            }
+            tiers.push(tier);
+        }
+        Ok(FeeSchedule::new(house, tiers))
+    }
+
+    /// Client that receives every fee, so the sum of all client totals
+    /// only changes by deposits, withdrawals and chargebacks.
+    pub fn house(&self) -> &ClientID {
+        &self.house
+    }
+
+    /// Fee charged for `amount` of the given type. It is never negative.
+    pub fn fee(&self, tx_type: &TxType, amount: Decimal) -> Decimal {
+        let tier = self
+            .tiers
+            .get(tx_type)
+            .and_then(|tiers| tiers.iter().rev().find(|tier| tier.lower_bound() <= amount));
+        match tier {
+            Some(tier) => tier.fee(amount).max(dec!(0)),
+            None => dec!(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fees::{FeeSchedule, FeeTier};
+    use crate::transactions::{ClientID, TxType};
+    use rust_decimal_macros::dec;
+    use csv::{ReaderBuilder, Trim};
+
+    fn schedule(data: &str) -> FeeSchedule {
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let tiers: Vec<FeeTier> = rdr.deserialize().map(|tier| tier.unwrap()).collect();
+        FeeSchedule::new(ClientID::new(0), tiers)
+    }
+
+    #[test]
+    fn check_fee_tiers() {
+        let fees = schedule("\
+type, from, flat, percent, min, max
+withdrawal, , 0.5, , ,
+deposit, 0, , 1.0, 0.1, 5
+deposit, 1000, 1, 0.5, , 4");
+
+        assert_eq!(fees.fee(&TxType::Withdrawal, dec!(20)), dec!(0.5));
+        assert_eq!(fees.fee(&TxType::Deposit, dec!(2)), dec!(0.1));
+        assert_eq!(fees.fee(&TxType::Deposit, dec!(200)), dec!(2));
+        assert_eq!(fees.fee(&TxType::Deposit, dec!(999)), dec!(5));
+        assert_eq!(fees.fee(&TxType::Deposit, dec!(1000)), dec!(4));
+        assert_eq!(fees.fee(&TxType::Deposit, dec!(100)), dec!(1));
+        assert_eq!(fees.fee(&TxType::Transfer, dec!(100)), dec!(0));
+    }
+}
@@ -0,0 +1,268 @@
+use csv::Writer;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::model::{Model, Row};
+use crate::transactions::TxType;
+
+/// SplitMix64, so that a seed gives the same rows on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` is not 0.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, rate: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+}
+
+/// Relative weights of deposits and withdrawals, like `deposit=60,withdrawal=40`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeMix {
+    pub deposit: u32,
+    pub withdrawal: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeMixError(String);
+
+impl fmt::Display for TypeMixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid type mix {}, expected deposit=<weight>,withdrawal=<weight>", self.0)
+    }
+}
+
+impl Error for TypeMixError {}
+
+impl FromStr for TypeMix {
+    type Err = TypeMixError;
+
+    fn from_str(value: &str) -> Result<TypeMix, TypeMixError> {
+        let mut mix = TypeMix { deposit: 0, withdrawal: 0 };
+        for weight in value.split(',') {
+            let error = || TypeMixError(value.to_string());
+            let (name, weight) = weight.split_once('=').ok_or_else(error)?;
+            let weight = weight.trim().parse().map_err(|_| error())?;
+            match name.trim() {
+                "deposit" => mix.deposit = weight,
+                "withdrawal" => mix.withdrawal = weight,
+                _ => return Err(error()),
+            }
+        }
+        match mix.deposit + mix.withdrawal {
+            0 => Err(TypeMixError(value.to_string())),
+            _ => Ok(mix),
+        }
+    }
+}
+
+/// - `dispute_rate`: share of the rows that dispute, resolve or charge back
+///   an earlier deposit or withdrawal;
+/// - `invalid_rate`: share of the rows that the engine rejects, like a
+///   withdrawal above the available funds or a resolve of a transaction
+///   that is not disputed. They are valid CSV, as a malformed row stops the
+///   engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub clients: u16,
+    pub rows: u32,
+    pub mix: TypeMix,
+    pub dispute_rate: f64,
+    pub invalid_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {
+            clients: 100,
+            rows: 1000,
+            mix: TypeMix { deposit: 60, withdrawal: 40 },
+            dispute_rate: 0.01,
+            invalid_rate: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// Writes random but plausible input rows. Every row is applied to the
+/// reference model as well, which then has the expected accounts.
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: Rng,
+    model: Model,
+    rows: u32,
+    next_tx: u32,
+    stored: Vec<(u16, u32)>,
+    disputed: Vec<(u16, u32)>,
+    resolved: Vec<(u16, u32)>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Generator {
+        let rng = Rng(config.seed);
+        Generator {
+            config,
+            rng,
+            model: Model::new(),
+            rows: 0,
+            next_tx: 1,
+            stored: Vec::new(),
+            disputed: Vec::new(),
+            resolved: Vec::new(),
+        }
+    }
+
+    pub fn get_model(&self) -> &Model {
+        &self.model
+    }
+
+    fn take(&mut self, ids: usize) -> Option<(u16, u32)> {
+        let list = match ids {
+            0 => &mut self.stored,
+            1 => &mut self.disputed,
+            _ => &mut self.resolved,
+        };
+        match list.len() {
+            0 => None,
+            len => Some(list.swap_remove(self.rng.below(len as u64) as usize)),
+        }
+    }
+
+    fn pick(&mut self, ids: usize) -> Option<(u16, u32)> {
+        let list = match ids {
+            0 => &self.stored,
+            1 => &self.disputed,
+            _ => &self.resolved,
+        };
+        match list.len() {
+            0 => None,
+            len => Some(list[self.rng.below(len as u64) as usize]),
+        }
+    }
+
+    fn client(&mut self) -> u16 {
+        1 + self.rng.below(self.config.clients.max(1) as u64) as u16
+    }
+
+    fn new_tx(&mut self) -> u32 {
+        let tx = self.next_tx;
+        self.next_tx += 1;
+        tx
+    }
+
+    /// A dispute, resolve or chargeback that moves one transaction to the
+    /// next step, if there is one to move.
+    fn dispute_row(&mut self) -> Option<Row> {
+        let first = self.rng.below(3) as usize;
+        for step in (0..3).map(|step| (first + step) % 3) {
+            if let Some((client, tx)) = self.take(step) {
+                let tx_type = match step {
+                    0 => TxType::Dispute,
+                    1 => TxType::Resolve,
+                    _ => TxType::Chargeback,
+                };
+                match step {
+                    0 => self.disputed.push((client, tx)),
+                    1 => self.resolved.push((client, tx)),
+                    _ => (),
+                }
+                return Some(Row { tx_type, client, tx, amount: None });
+            }
+        }
+        None
+    }
+
+    fn invalid_row(&mut self) -> Row {
+        let step = self.rng.below(4);
+        let (tx_type, ids) = match step {
+            0 => (TxType::Resolve, self.pick(0)),
+            1 => (TxType::Chargeback, self.pick(1)),
+            _ => (TxType::Dispute, None),
+        };
+        match (step, ids) {
+            (0 | 1, Some((client, tx))) => Row { tx_type, client, tx, amount: None },
+            (2, _) => {
+                let client = self.client();
+                Row { tx_type: TxType::Dispute, client, tx: 0, amount: None }
+            }
+            _ => {
+                let client = self.client();
+                let amount = self.model.available(client) + self.amount();
+                Row { tx_type: TxType::Withdrawal, client, tx: self.new_tx(), amount: Some(amount) }
+            }
+        }
+    }
+
+    /// Up to 100, with 4 decimal places.
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.rng.below(1_000_000) as i64 + 1, 4)
+    }
+
+    fn transaction_row(&mut self) -> Row {
+        let client = self.client();
+        let weights = self.config.mix.deposit as u64 + self.config.mix.withdrawal as u64;
+        let available = self.model.available(client);
+        let withdrawal = self.rng.below(weights) >= self.config.mix.deposit as u64;
+        let share = Decimal::new(self.rng.below(10_000) as i64 + 1, 4);
+        let withdrawn = (available * share).round_dp_with_strategy(4, RoundingStrategy::ToZero);
+        let (tx_type, amount) = match withdrawal && !withdrawn.is_zero() {
+            true => (TxType::Withdrawal, withdrawn),
+            false => (TxType::Deposit, self.amount()),
+        };
+        let tx = self.new_tx();
+        self.stored.push((client, tx));
+        Row { tx_type, client, tx, amount: Some(amount) }
+    }
+
+    fn next_row(&mut self) -> Row {
+        if self.rng.chance(self.config.invalid_rate) {
+            return self.invalid_row();
+        }
+        if self.rng.chance(self.config.dispute_rate) {
+            if let Some(row) = self.dispute_row() {
+                return row;
+            }
+        }
+        self.transaction_row()
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        if self.rows == self.config.rows {
+            return None;
+        }
+        self.rows += 1;
+        let row = self.next_row();
+        self.model.apply(&row);
+        Some(row)
+    }
+}
+
+pub fn output_rows<W: Write>(
+    wtr: &mut Writer<W>,
+    generator: &mut Generator,
+) -> Result<(), Box<dyn Error>> {
+    for row in generator {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use csv::{Reader, Writer};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::admin::{AdminTemplate, AdminTxType};
+use crate::transactions::{ClientID, TransactionTemplate, TxId, TxType};
+
+/// What the index knows about a row before it is processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowStatus {
+    New,
+    /// The same row was already processed, in this run or an earlier one.
+    Duplicate,
+    /// A processed row has the same tx id and type but other content.
+    Conflict,
+}
+
+/// The type of an indexed row: of the transactions file or of the admin
+/// file. An admin row never clashes with a transaction of an unknown type
+/// of the same name.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum RowType {
+    Transaction(TxType),
+    Admin(AdminTxType),
+}
+
+/// A row that reused a tx id with different content and was skipped.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub line: u64,
+    #[serde(rename = "type")]
+    pub tx_type: RowType,
+    pub client: ClientID,
+    pub tx: TxId,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RowCounts {
+    pub new: u64,
+    pub duplicate: u64,
+    pub conflicting: u64,
+}
+
+impl fmt::Display for RowCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} new, {} duplicate, {} conflicting rows", self.new, self.duplicate, self.conflicting)
+    }
+}
+
+/// One row of the persisted index. Indexes saved before admin rows were
+/// kept have no `admin` column.
+#[derive(Debug, Serialize, Deserialize)]
+struct SeenRow {
+    tx: TxId,
+    client: ClientID,
+    #[serde(rename = "type")]
+    tx_type: String,
+    fingerprint: u64,
+    #[serde(default)]
+    admin: bool,
+}
+
+fn hash(content: &str) -> u64 {
+    content.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn amount_field(amount: Option<Decimal>) -> String {
+    amount.map(|amount| amount.normalize().to_string()).unwrap_or_default()
+}
+
+/// FNV-1a over the fields of the row, written out one by one: the type
+/// name, the client, tx and recipient as numbers, the amount normalized, so
+/// `1.0` and `1` are the same row, and the timestamp. It has to stay stable,
+/// the index is kept across runs.
+pub fn fingerprint(row: &TransactionTemplate) -> u64 {
+    let tx_type = match &row.tx_type {
+        TxType::Unknown(name) => name.as_str(),
+        tx_type => tx_type.name(),
+    };
+    let content = format!(
+        "{}|{}|{}|{}|{}|{}",
+        tx_type,
+        row.get_client().value(),
+        row.get_tx().value(),
+        amount_field(row.get_amount()),
+        row.get_to_client().map(|client| client.value().to_string()).unwrap_or_default(),
+        row.get_timestamp().map(|timestamp| timestamp.to_string()).unwrap_or_default(),
+    );
+    hash(&content)
+}
+
+/// Like `fingerprint`, for a row of the admin file: the type name, the
+/// client, tx and amount, the reason and the operator.
+pub fn admin_fingerprint(row: &AdminTemplate) -> u64 {
+    let content = format!(
+        "{}|{}|{}|{}|{}|{}",
+        row.tx_type.name(),
+        row.get_client().value(),
+        row.get_tx().value(),
+        amount_field(row.get_amount()),
+        row.get_reason(),
+        row.get_operator(),
+    );
+    hash(&content)
+}
+
+/// Rows already processed, keyed by tx id, client and type: a dispute
+/// reuses the tx id of its deposit, but is a row of its own, and clients can
+/// reuse each other's tx ids. Rows of the admin file are kept too.
+#[derive(Debug, Default)]
+pub struct RowIndex {
+    seen: HashMap<(TxId, ClientID, RowType), u64>,
+    counts: RowCounts,
+    conflicts: Vec<Conflict>,
+}
+
+impl RowIndex {
+    pub fn new() -> RowIndex {
+        RowIndex::default()
+    }
+
+    /// Loads an index written by `save`, with `tx, client, type,
+    /// fingerprint, admin` rows.
+    pub fn load<R: Read>(rdr: &mut Reader<R>) -> Result<RowIndex, Box<dyn Error>> {
+        let mut index = RowIndex::new();
+        for result in rdr.deserialize() {
+            let row: SeenRow = result?;
+            let tx_type = match row.admin {
+                true => match AdminTxType::from_name(&row.tx_type) {
+                    Some(tx_type) => RowType::Admin(tx_type),
+                    None => return Err(format!("unknown admin type {} in the row index", row.tx_type).into()),
+                },
+                false => RowType::Transaction(
+                    TxType::from_name(row.tx_type.as_bytes()).unwrap_or(TxType::Unknown(row.tx_type)),
+                ),
+            };
+            index.seen.insert((row.tx, row.client, tx_type), row.fingerprint);
+        }
+        Ok(index)
+    }
+
+    pub fn save<W: Write>(&self, wtr: &mut Writer<W>) -> Result<(), Box<dyn Error>> {
+        for ((tx, client, tx_type), fingerprint) in self.seen.iter() {
+            let (name, admin) = match tx_type {
+                RowType::Transaction(TxType::Unknown(name)) => (name.clone(), false),
+                RowType::Transaction(tx_type) => (tx_type.name().to_string(), false),
+                RowType::Admin(tx_type) => (tx_type.name().to_string(), true),
+            };
+            let row = SeenRow { tx: tx.clone(), client: client.clone(), tx_type: name, fingerprint: *fingerprint, admin };
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Classifies `row` and remembers it if it is new. Only new rows should
+    /// be processed.
+    pub fn check(&mut self, line: u64, row: &TransactionTemplate) -> RowStatus {
+        let key = (row.get_tx().clone(), row.get_client().clone(), RowType::Transaction(row.tx_type.clone()));
+        self.check_key(line, key, fingerprint(row))
+    }
+
+    /// Like `check`, for a row of the admin file.
+    pub fn check_admin(&mut self, line: u64, row: &AdminTemplate) -> RowStatus {
+        let key = (row.get_tx().clone(), row.get_client().clone(), RowType::Admin(row.tx_type.clone()));
+        self.check_key(line, key, admin_fingerprint(row))
+    }
+
+    fn check_key(&mut self, line: u64, key: (TxId, ClientID, RowType), fingerprint: u64) -> RowStatus {
+        let status = match self.seen.get(&key) {
+            None => RowStatus::New,
+            Some(seen) if *seen == fingerprint => RowStatus::Duplicate,
+            Some(_) => RowStatus::Conflict,
+        };
+        match status {
+            RowStatus::New => {
+                self.seen.insert(key, fingerprint);
+                self.counts.new += 1;
+            }
+            RowStatus::Duplicate => self.counts.duplicate += 1,
+            RowStatus::Conflict => {
+                self.counts.conflicting += 1;
+                let (tx, client, tx_type) = key;
+                self.conflicts.push(Conflict { line, tx_type, client, tx });
+            }
+        }
+        status
+    }
+
+    pub fn get_counts(&self) -> RowCounts {
+        self.counts
+    }
+
+    pub fn get_conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    pub fn output_conflicts<W: Write>(&self, wtr: &mut Writer<W>) -> Result<(), Box<dyn Error>> {
+        for conflict in self.conflicts.iter() {
+            wtr.serialize(conflict)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use csv::{ByteRecord, Position, Reader, ReaderBuilder, StringRecord, Trim};
+use rayon::prelude::*;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::{self, FromStr};
+
+use serde::Deserialize;
+
+use crate::transactions::{ClientID, TransactionTemplate, TxId, TxType};
+
+/// Columns of the files that are read by `parse_row`, in this order.
+pub const COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Size of the pieces a file in memory is split into, each one parsed by
+/// one thread.
+pub const CHUNK_BYTES: usize = 4 << 20;
+
+/// A row that could not be read, by `parse_row` or `Layout::template`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowError {
+    /// A field that is empty or could not be read.
+    Field { line: u64, field: &'static str, value: String },
+    /// A row with another number of fields than the header, or than the
+    /// first row of a file without headers. Shown like the error of a csv
+    /// reader that is not flexible.
+    Width { position: Position, fields: usize, expected: usize },
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowError::Field { line, field, value } if value.is_empty() => write!(f, "line {}: missing {}", line, field),
+            RowError::Field { line, field, value } => write!(f, "line {}: invalid {} '{}'", line, field, value),
+            RowError::Width { position, fields, expected } => write!(
+                f,
+                "CSV error: record {} (line: {}, byte: {}): found record with {} fields, but the previous record has {} fields",
+                position.record(),
+                position.line(),
+                position.byte(),
+                fields,
+                expected
+            ),
+        }
+    }
+}
+
+impl Error for RowError {}
+
+fn field(record: &ByteRecord, index: usize) -> &[u8] {
+    record.get(index).unwrap_or_default().trim_ascii()
+}
+
+fn invalid(line: u64, index: usize, value: &[u8]) -> RowError {
+    RowError::Field { line, field: COLUMNS[index], value: String::from_utf8_lossy(value).into_owned() }
+}
+
+fn parse_number<T: FromStr>(record: &ByteRecord, index: usize, line: u64) -> Result<T, RowError> {
+    let value = field(record, index);
+    str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid(line, index, value))
+}
+
+/// Reads an amount as written, with no rounding through floating point.
+/// Amounts like `1e3` are read too.
+pub fn parse_decimal(text: &str) -> Result<Decimal, rust_decimal::Error> {
+    match text.contains(['e', 'E']) {
+        true => Decimal::from_scientific(text),
+        false => Decimal::from_str(text),
+    }
+}
+
+fn parse_amount(record: &ByteRecord, line: u64) -> Result<Option<Decimal>, RowError> {
+    let value = field(record, 3);
+    if value.is_empty() {
+        return Ok(None);
+    }
+    str::from_utf8(value)
+        .ok()
+        .and_then(|text| parse_decimal(text).ok())
+        .map(Some)
+        .ok_or_else(|| invalid(line, 3, value))
+}
+
+#[derive(Debug, Deserialize)]
+struct AliasRow {
+    alias: String,
+    #[serde(rename = "type")]
+    tx_type: String,
+}
+
+/// Other names of the transaction types, loaded from a CSV file with
+/// `alias, type` rows:
+///
+/// ```text
+/// alias, type
+/// dep, deposit
+/// cb, chargeback
+/// ```
+///
+/// Type names and aliases are matched in any case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeAliases {
+    aliases: Vec<(String, TxType)>,
+}
+
+impl TypeAliases {
+    pub fn new(aliases: Vec<(String, TxType)>) -> TypeAliases {
+        TypeAliases { aliases }
+    }
+
+    pub fn from_path(path: &Path) -> Result<TypeAliases, Box<dyn Error>> {
+        TypeAliases::load(&mut ReaderBuilder::new().trim(Trim::All).from_path(path)?)
+    }
+
+    pub fn load<R: Read>(rdr: &mut Reader<R>) -> Result<TypeAliases, Box<dyn Error>> {
+        let mut aliases = Vec::new();
+        for result in rdr.deserialize() {
+            let row: AliasRow = result?;
+            match TxType::from_name(row.tx_type.as_bytes()) {
+                Some(tx_type) => aliases.push((row.alias, tx_type)),
+                None => return Err(format!("unknown type {} for alias {}", row.tx_type, row.alias).into()),
+            }
+        }
+        Ok(TypeAliases { aliases })
+    }
+
+    /// The type named or aliased `name`, or `TxType::Unknown` with `name` as
+    /// it was written.
+    pub fn resolve(&self, name: &[u8]) -> TxType {
+        TxType::from_name(name)
+            .or_else(|| {
+                let alias = self.aliases.iter().find(|(alias, _)| alias.as_bytes().eq_ignore_ascii_case(name));
+                alias.map(|(_, tx_type)| tx_type.clone())
+            })
+            .unwrap_or_else(|| TxType::Unknown(String::from_utf8_lossy(name).into_owned()))
+    }
+}
+
+/// Reads a row of a file with the columns of `COLUMNS`. Fields are trimmed
+/// and an empty amount is `None`. A row with fewer or more fields is an
+/// error. Types are looked up in `aliases` unless they are written in lower
+/// case. Nothing is allocated unless the row is invalid or of an unknown
+/// type.
+pub fn parse_row(record: &ByteRecord, line: u64, aliases: &TypeAliases) -> Result<TransactionTemplate, RowError> {
+    if record.len() != COLUMNS.len() {
+        let mut position = record.position().cloned().unwrap_or_else(Position::new);
+        position.set_line(line);
+        return Err(RowError::Width { position, fields: record.len(), expected: COLUMNS.len() });
+    }
+    let tx_type = match field(record, 0) {
+        b"deposit" => TxType::Deposit,
+        b"withdrawal" => TxType::Withdrawal,
+        b"transfer" => TxType::Transfer,
+        b"dispute" => TxType::Dispute,
+        b"resolve" => TxType::Resolve,
+        b"chargeback" => TxType::Chargeback,
+        value => aliases.resolve(value),
+    };
+    let client = ClientID::new(parse_number(record, 1, line)?);
+    let tx = TxId::new(parse_number(record, 2, line)?);
+    let amount = parse_amount(record, line)?;
+    Ok(TransactionTemplate::new(tx_type, client, tx, amount))
+}
+
+/// The error of a row that `parse_row` could not read, as serde gives it:
+/// the row is deserialized again, so the error names its record, line and
+/// byte like the error of a csv reader. `err` is kept for rows of another
+/// width and for rows that are not UTF-8.
+pub fn row_error(record: &ByteRecord, err: RowError) -> Box<dyn Error> {
+    if let RowError::Width { .. } = err {
+        return err.into();
+    }
+    let mut row = match StringRecord::from_byte_record(record.clone()) {
+        Ok(row) => row,
+        Err(_) => return err.into(),
+    };
+    row.trim();
+    row.set_position(record.position().cloned());
+    match row.deserialize::<TransactionTemplate>(Some(&StringRecord::from(COLUMNS.to_vec()))) {
+        Ok(_) => err.into(),
+        Err(serde) => serde.into(),
+    }
+}
+
+/// The rows of a piece of a file with their line numbers, up to the first
+/// row that could not be read. The position in the error is that of the
+/// piece.
+pub struct Batch {
+    pub rows: Vec<(u64, TransactionTemplate)>,
+    pub error: Option<Box<dyn Error + Send + Sync>>,
+}
+
+/// Whether the byte at `at` is the first one of the end of a line that is
+/// not empty, where the csv reader starts the next row. A row after `\r\n`
+/// or after empty lines gets the line of that byte, so pieces cut there are
+/// read like the whole file.
+fn ends_row(data: &[u8], at: usize) -> bool {
+    let is_end = |byte: &u8| *byte == b'\n' || *byte == b'\r';
+    is_end(&data[at]) && at > 0 && !is_end(&data[at - 1])
+}
+
+/// Splits `data` into pieces of at least `size` bytes, each ending with the
+/// first byte of the end of a line that is not empty, apart from the last
+/// one.
+pub fn split_lines(data: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let end = (size..rest.len()).find(|at| ends_row(rest, *at)).map_or(rest.len(), |end| end + 1);
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// Reads the rows of `chunk`, a piece of a file with the columns of
+/// `COLUMNS` that starts at line `first_line`.
+pub fn parse_chunk(chunk: &[u8], first_line: u64, delimiter: u8, aliases: &TypeAliases) -> Batch {
+    let mut rdr = ReaderBuilder::new().delimiter(delimiter).has_headers(false).flexible(true).from_reader(chunk);
+    let mut record = ByteRecord::new();
+    let mut batch = Batch { rows: Vec::new(), error: None };
+    loop {
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {
+                let line = first_line + record.position().map_or(1, |position| position.line()) - 1;
+                match parse_row(&record, line, aliases) {
+                    Ok(row) => batch.rows.push((line, row)),
+                    Err(err) => {
+                        batch.error = Some(err.into());
+                        break;
+                    }
+                }
+            }
+            Ok(false) => break,
+            Err(err) => {
+                batch.error = Some(err.into());
+                break;
+            }
+        }
+    }
+    batch
+}
+
+/// Parses `chunks` in parallel, the first one starting at line `first_line`.
+/// Returns the batches in the order of the chunks and the line after them.
+pub fn parse_chunks(chunks: &[&[u8]], first_line: u64, delimiter: u8, aliases: &TypeAliases) -> (Vec<Batch>, u64) {
+    let lines: Vec<u64> = chunks
+        .par_iter()
+        .map(|chunk| chunk.iter().filter(|&&byte| byte == b'\n').count() as u64)
+        .collect();
+    let first_lines: Vec<u64> = lines
+        .iter()
+        .scan(first_line, |line, count| {
+            let first = *line;
+            *line += count;
+            Some(first)
+        })
+        .collect();
+    let batches = chunks
+        .par_iter()
+        .zip(first_lines)
+        .map(|(chunk, first_line)| parse_chunk(chunk, first_line, delimiter, aliases))
+        .collect();
+    (batches, first_line + lines.iter().sum::<u64>())
+}
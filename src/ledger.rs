@@ -0,0 +1,206 @@
+use csv::Writer;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::transactions::{Client, ClientID, TxId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Account {
+    ClientAvailable(ClientID),
+    ClientHeld(ClientID),
+    ExternalSettlement,
+    ChargebackLoss,
+    ManualAdjustment,
+}
+
+impl Account {
+    fn name(&self) -> &'static str {
+        match self {
+            Account::ClientAvailable(_) => "client_available",
+            Account::ClientHeld(_) => "client_held",
+            Account::ExternalSettlement => "external_settlement",
+            Account::ChargebackLoss => "chargeback_loss",
+            Account::ManualAdjustment => "manual_adjustment",
+        }
+    }
+
+    pub fn client(&self) -> Option<&ClientID> {
+        match self {
+            Account::ClientAvailable(client) | Account::ClientHeld(client) => Some(client),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    pub account: Account,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// Postings produced by one engine operation. Client accounts are owed to
+/// the clients, so a credit increases a client balance and a debit decreases it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub tx: TxId,
+    pub kind: &'static str,
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    pub fn new(tx: TxId, kind: &'static str) -> JournalEntry {
+        JournalEntry {
+            tx,
+            kind,
+            postings: Vec::new(),
+        }
+    }
+
+    pub fn debit(mut self, account: Account, amount: Decimal) -> JournalEntry {
+        if !amount.is_zero() {
+            self.postings.push(Posting { account, debit: amount, credit: dec!(0) });
+        }
+        self
+    }
+
+    pub fn credit(mut self, account: Account, amount: Decimal) -> JournalEntry {
+        if !amount.is_zero() {
+            self.postings.push(Posting { account, debit: dec!(0), credit: amount });
+        }
+        self
+    }
+
+    pub fn debits(&self) -> Decimal {
+        self.postings.iter().map(|posting| posting.debit).sum()
+    }
+
+    pub fn credits(&self) -> Decimal {
+        self.postings.iter().map(|posting| posting.credit).sum()
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.debits() == self.credits()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrialBalance {
+    pub debits: Decimal,
+    pub credits: Decimal,
+    pub unbalanced_entries: usize,
+}
+
+impl TrialBalance {
+    pub fn is_balanced(&self) -> bool {
+        self.debits == self.credits && self.unbalanced_entries == 0
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JournalRow<'a> {
+    entry: usize,
+    tx: &'a TxId,
+    kind: &'a str,
+    account: &'a str,
+    client: Option<&'a ClientID>,
+    debit: Decimal,
+    credit: Decimal,
+}
+
+/// Every entry posted during a run. The trial balance totals are kept even
+/// if the entries themselves are not, so it can be checked for any run.
+#[derive(Debug, Default)]
+pub struct Journal {
+    keep_entries: bool,
+    entries: Vec<JournalEntry>,
+    trial_balance: Option<TrialBalance>,
+}
+
+impl Journal {
+    pub fn new(keep_entries: bool) -> Journal {
+        Journal {
+            keep_entries,
+            entries: Vec::new(),
+            trial_balance: None,
+        }
+    }
+
+    pub fn record(&mut self, entry: JournalEntry) {
+        let trial_balance = self.trial_balance.get_or_insert(TrialBalance {
+            debits: dec!(0),
+            credits: dec!(0),
+            unbalanced_entries: 0,
+        });
+        trial_balance.debits += entry.debits();
+        trial_balance.credits += entry.credits();
+        if !entry.is_balanced() {
+            trial_balance.unbalanced_entries += 1;
+        }
+        if self.keep_entries {
+            self.entries.push(entry);
+        }
+    }
+
+    pub fn get_entries(&self) -> &Vec<JournalEntry> {
+        &self.entries
+    }
+
+    pub fn trial_balance(&self) -> TrialBalance {
+        self.trial_balance.clone().unwrap_or(TrialBalance {
+            debits: dec!(0),
+            credits: dec!(0),
+            unbalanced_entries: 0,
+        })
+    }
+
+    /// Client balances computed from the kept entries only.
+    pub fn client_balances(&self) -> HashMap<ClientID, Client> {
+        let mut clients: HashMap<ClientID, Client> = HashMap::new();
+        for posting in self.entries.iter().flat_map(|entry| entry.postings.iter()) {
+            apply_posting(&mut clients, posting);
+        }
+        clients
+    }
+
+    pub fn export<W: Write>(&self, wtr: &mut Writer<W>) -> Result<(), Box<dyn Error>> {
+        for (i, entry) in self.entries.iter().enumerate() {
+            for posting in entry.postings.iter() {
+                wtr.serialize(JournalRow {
+                    entry: i + 1,
+                    tx: &entry.tx,
+                    kind: entry.kind,
+                    account: posting.account.name(),
+                    client: posting.account.client(),
+                    debit: posting.debit,
+                    credit: posting.credit,
+                })?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Applies a posting to the balances of the client it belongs to.
+pub fn apply_posting(clients: &mut HashMap<ClientID, Client>, posting: &Posting) {
+    let client_id = match posting.account.client() {
+        Some(client_id) => client_id,
+        None => return,
+    };
+    let client = clients
+        .entry(client_id.clone())
+        .or_insert_with(|| Client::empty(client_id.clone()));
+    let change = posting.credit - posting.debit;
+    match posting.account {
+        Account::ClientAvailable(_) => client.available += change,
+        Account::ClientHeld(_) => client.held += change,
+        _ => (),
+    }
+    client.total = client.available + client.held;
+}
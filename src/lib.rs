@@ -0,0 +1,23 @@
+pub mod admin;
+pub mod deltas;
+pub mod disputes;
+pub mod events;
+pub mod fees;
+pub mod generator;
+pub mod idempotency;
+pub mod ingest;
+pub mod ledger;
+pub mod limits;
+pub mod model;
+pub mod proccess_input_output;
+pub mod profile;
+pub mod projection;
+pub mod rejects;
+pub mod reconcile;
+pub mod retention;
+pub mod rules;
+pub mod statement;
+pub mod summary;
+pub mod transactions;
+pub mod transactions_info;
+pub mod tx_store;
@@ -0,0 +1,150 @@
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitKind {
+    MaxWithdrawal,
+    MaxDailyWithdrawalCount,
+    MaxDailyWithdrawalVolume,
+    MaxNewAccountDeposit,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LimitKind::MaxWithdrawal => "max_withdrawal",
+            LimitKind::MaxDailyWithdrawalCount => "max_daily_withdrawal_count",
+            LimitKind::MaxDailyWithdrawalVolume => "max_daily_withdrawal_volume",
+            LimitKind::MaxNewAccountDeposit => "max_new_account_deposit",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LimitRow {
+    limit: String,
+    value: Decimal,
+}
+
+/// Risk limits loaded from a CSV file with `limit, value` rows:
+///
+/// ```text
+/// limit, value
+/// max_withdrawal, 1000
+/// max_daily_withdrawal_count, 3
+/// max_daily_withdrawal_volume, 2500
+/// max_new_account_deposit, 500
+/// new_account_days, 7
+/// rows_per_day, 10000
+/// ```
+///
+/// A day is taken from the optional `timestamp` column (unix seconds). Rows
+/// without a timestamp use their position in the input, `rows_per_day` rows
+/// per day, or a single day for the whole input if it is not set.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub max_withdrawal: Option<Decimal>,
+    pub max_daily_withdrawal_count: Option<u64>,
+    pub max_daily_withdrawal_volume: Option<Decimal>,
+    pub max_new_account_deposit: Option<Decimal>,
+    pub new_account_days: u64,
+    pub rows_per_day: Option<u64>,
+}
+
+fn whole_number(row: &LimitRow) -> Result<u64, Box<dyn Error>> {
+    if !row.value.fract().is_zero() || row.value < dec!(0) {
+        return Err(format!("limit {} must be a whole number", row.limit).into());
+    }
+    Ok(row.value.to_string().parse()?)
+}
+
+impl Limits {
+    pub fn from_path(path: &Path) -> Result<Limits, Box<dyn Error>> {
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+        let mut limits = Limits { new_account_days: 1, ..Limits::default() };
+        for result in rdr.deserialize() {
+            let row: LimitRow = result?;
+            match row.limit.as_str() {
+                "max_withdrawal" => limits.max_withdrawal = Some(row.value),
+                "max_daily_withdrawal_count" => limits.max_daily_withdrawal_count = Some(whole_number(&row)?),
+                "max_daily_withdrawal_volume" => limits.max_daily_withdrawal_volume = Some(row.value),
+                "max_new_account_deposit" => limits.max_new_account_deposit = Some(row.value),
+                "new_account_days" => limits.new_account_days = whole_number(&row)?,
+                "rows_per_day" => limits.rows_per_day = Some(whole_number(&row)?).filter(|rows| *rows > 0),
+                limit => return Err(format!("unknown limit {}", limit).into()),
+            }
+        }
+        Ok(limits)
+    }
+
+    /// Day of a row, from its timestamp or from its line in the input.
+    pub fn day(&self, timestamp: Option<u64>, line: u64) -> u64 {
+        match (timestamp, self.rows_per_day) {
+            (Some(timestamp), _) => timestamp / SECONDS_PER_DAY,
+            (None, Some(rows_per_day)) => line / rows_per_day,
+            (None, None) => 0,
+        }
+    }
+
+    pub fn check_withdrawal(&self, counters: &LimitCounters, day: u64, amount: Decimal) -> Option<LimitKind> {
+        let (count, volume) = counters.withdrawals_on(day);
+        if self.max_withdrawal.is_some_and(|max| amount > max) {
+            return Some(LimitKind::MaxWithdrawal);
+        }
+        if self.max_daily_withdrawal_count.is_some_and(|max| count + 1 > max) {
+            return Some(LimitKind::MaxDailyWithdrawalCount);
+        }
+        if self.max_daily_withdrawal_volume.is_some_and(|max| volume + amount > max) {
+            return Some(LimitKind::MaxDailyWithdrawalVolume);
+        }
+        None
+    }
+
+    pub fn check_deposit(&self, counters: &LimitCounters, day: u64, amount: Decimal) -> Option<LimitKind> {
+        let is_new = counters.first_day.is_none_or(|first_day| day < first_day + self.new_account_days);
+        if is_new && self.max_new_account_deposit.is_some_and(|max| amount > max) {
+            return Some(LimitKind::MaxNewAccountDeposit);
+        }
+        None
+    }
+}
+
+/// Per client usage of the limits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LimitCounters {
+    pub first_day: Option<u64>,
+    pub day: u64,
+    pub withdrawal_count: u64,
+    pub withdrawal_volume: Decimal,
+}
+
+impl LimitCounters {
+    fn withdrawals_on(&self, day: u64) -> (u64, Decimal) {
+        if self.day == day {
+            (self.withdrawal_count, self.withdrawal_volume)
+        } else {
+            (0, dec!(0))
+        }
+    }
+
+    pub fn record_deposit(&mut self, day: u64) {
+        self.first_day.get_or_insert(day);
+    }
+
+    pub fn record_withdrawal(&mut self, day: u64, amount: Decimal) {
+        let (count, volume) = self.withdrawals_on(day);
+        self.day = day;
+        self.withdrawal_count = count + 1;
+        self.withdrawal_volume = volume + amount;
+        self.first_day.get_or_insert(day);
+    }
+}
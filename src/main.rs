@@ -1,32 +1,478 @@
-use csv::{ReaderBuilder, Trim, Writer};
+use csv::{Reader, ReaderBuilder, Trim, Writer};
+use rayon::ThreadPoolBuilder;
+use std::collections::HashMap;
 use std::env;
-use std::io;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
 use std::path::Path;
 use std::process;
+use std::sync::atomic::{AtomicI32, Ordering};
 
-use crate::proccess_input_output::{output_client_data, proccess_input};
-use crate::transactions_info::TransactionsInfo;
+use kraken_homework::admin::Operators;
+use kraken_homework::deltas::DeltaStream;
+use kraken_homework::disputes::{open_disputes, output_disputes_csv, output_disputes_json};
+use kraken_homework::events::{EngineObserver, NdjsonEventLog};
+use kraken_homework::fees::FeeSchedule;
+use kraken_homework::generator::{output_rows, Generator, GeneratorConfig, TypeMixError};
+use kraken_homework::idempotency::RowIndex;
+use kraken_homework::ingest::TypeAliases;
+use kraken_homework::limits::Limits;
+use kraken_homework::profile::Profile;
+use kraken_homework::projection::replay;
+use kraken_homework::rejects::output_rejects;
+use kraken_homework::reconcile::{output_differences, read_accounts, reconcile};
+use kraken_homework::retention::DisputeWindow;
+use kraken_homework::rules::RuleEngine;
+use kraken_homework::proccess_input_output::{
+    output_audit, output_client_data, output_clients, proccess_admin_input, proccess_file,
+};
+use kraken_homework::statement::{client_statement, output_statement_csv, output_statement_json};
+use kraken_homework::summary::Summary;
+use kraken_homework::transactions::{Client, ClientID, TxId};
+use kraken_homework::transactions_info::TransactionsInfo;
 
-pub mod proccess_input_output;
-pub mod transactions;
-pub mod transactions_info;
+/// Exit code of `exit_with_error`: 1, or 2 for `reconcile`, which exits
+/// with 1 when the accounts differ.
+static ERROR_CODE: AtomicI32 = AtomicI32::new(1);
 
+fn exit_with_error(err: Box<dyn Error>) -> ! {
+    println!("Error: {}", err);
+    process::exit(ERROR_CODE.load(Ordering::Relaxed));
+}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let filename = Path::new(&args[1]);
-    let mut wtr = Writer::from_writer(io::stdout());
-    let mut rdr = ReaderBuilder::new()
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .map(|i| args.get(i + 1).expect("Option value is missing."))
+}
+
+/// For options whose value is optional: `None` if `name` is not given,
+/// `Some(None)` if it is not followed by a value.
+fn optional_value<'a>(args: &'a [String], name: &str) -> Option<Option<&'a String>> {
+    args.iter()
+        .position(|arg| arg == name)
+        .map(|i| args.get(i + 1).filter(|value| !value.starts_with("--")))
+}
+
+fn parse_client_ids(value: &str) -> Vec<ClientID> {
+    value
+        .split(',')
+        .map(|id| ClientID::new(id.trim().parse().expect("Client must be a client id.")))
+        .collect()
+}
+
+/// `--profiles <file> --partner <name>` reads the input as the partner
+/// writes it, otherwise it has the default layout. `--type-aliases <file>`
+/// adds other names of the types.
+fn partner_profile(args: &[String]) -> Result<Profile, Box<dyn Error>> {
+    let mut profile = match (option_value(args, "--profiles"), option_value(args, "--partner")) {
+        (Some(profiles), Some(partner)) => Profile::from_path(Path::new(profiles), partner)?,
+        (None, Some(_)) => return Err("--partner needs --profiles".into()),
+        _ => Profile::default(),
+    };
+    if let Some(aliases) = option_value(args, "--type-aliases") {
+        profile.aliases = TypeAliases::from_path(Path::new(aliases))?;
+    }
+    Ok(profile)
+}
+
+fn proccess_input_file(args: &[String], transactions_info: &mut TransactionsInfo) -> Result<(), Box<dyn Error>> {
+    let profile = partner_profile(args)?;
+    let file = File::open(Path::new(args.first().expect("Input file is missing.")))?;
+    proccess_file(file, &profile, transactions_info)
+}
+
+fn open_csv(filename: &Path) -> Reader<File> {
+    ReaderBuilder::new()
         .trim(Trim::All)
-        .from_path(filename).expect("Something went wrong reading the file.");
+        .flexible(true)
+        .from_path(filename).expect("Something went wrong reading the file.")
+}
+
+const STATE_ROWS: &str = "rows.csv";
+const STATE_EVENTS: &str = "events.ndjson";
+
+/// Loads what `--state <dir>` kept from earlier runs: the accounts are
+/// replayed from the event log and the processed rows are indexed, so rows
+/// that were sent again are skipped.
+fn load_state(state: &Path, transactions_info: &mut TransactionsInfo) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(state)?;
+    let rows_path = state.join(STATE_ROWS);
+    let row_index = if rows_path.exists() {
+        RowIndex::load(&mut ReaderBuilder::new().trim(Trim::All).from_path(rows_path)?)?
+    } else {
+        RowIndex::new()
+    };
+    transactions_info.set_row_index(row_index);
+    let events_path = state.join(STATE_EVENTS);
+    if events_path.exists() {
+        replay(BufReader::new(File::open(events_path)?), transactions_info)?;
+    }
+    transactions_info.keep_events();
+    Ok(())
+}
+
+/// Writes the state back once the whole input was processed, so a failed
+/// run leaves the state of the last good run.
+fn save_state(state: &Path, transactions_info: &TransactionsInfo) -> Result<(), Box<dyn Error>> {
+    let events = OpenOptions::new().create(true).append(true).open(state.join(STATE_EVENTS))?;
+    let mut event_log = NdjsonEventLog::new(BufWriter::new(events));
+    for event in transactions_info.get_events() {
+        event_log.on_event(event)?;
+    }
+    event_log.flush()?;
+    if let Some(row_index) = transactions_info.get_row_index() {
+        row_index.save(&mut Writer::from_path(state.join(STATE_ROWS))?)?;
+    }
+    Ok(())
+}
+
+/// Sets up the engine from the options shared by all commands.
+fn configure(args: &[String]) -> TransactionsInfo {
     let mut transactions_info = TransactionsInfo::new();
+    if let Some(fees) = option_value(args, "--fees") {
+        let house = option_value(args, "--house-client")
+            .map(|id| id.parse().expect("House client must be a client id."))
+            .unwrap_or(0);
+        match FeeSchedule::from_path(ClientID::new(house), Path::new(fees)) {
+            Ok(fee_schedule) => transactions_info.set_fee_schedule(fee_schedule),
+            Err(err) => exit_with_error(err),
+        }
+    }
+    if let Some(limits) = option_value(args, "--limits") {
+        match Limits::from_path(Path::new(limits)) {
+            Ok(limits) => transactions_info.set_limits(limits),
+            Err(err) => exit_with_error(err),
+        }
+    }
+    if let Some(rules) = option_value(args, "--rules") {
+        match RuleEngine::from_path(Path::new(rules)) {
+            Ok(rules) => transactions_info.set_rules(rules),
+            Err(err) => exit_with_error(err),
+        }
+    }
+    if let Some(operators) = option_value(args, "--operators") {
+        match Operators::from_path(Path::new(operators)) {
+            Ok(operators) => transactions_info.set_operators(operators),
+            Err(err) => exit_with_error(err),
+        }
+    }
+    if option_value(args, "--journal").is_some() {
+        transactions_info.keep_journal();
+    }
+    if option_value(args, "--rejects").is_some() {
+        transactions_info.keep_rejects();
+    }
+    if let Some(events) = option_value(args, "--events") {
+        match File::create(events) {
+            Ok(file) => transactions_info.register_observer(Box::new(NdjsonEventLog::new(BufWriter::new(file)))),
+            Err(err) => exit_with_error(err.into()),
+        }
+    }
+    let window_rows = option_value(args, "--dispute-window-rows")
+        .map(|rows| DisputeWindow::Rows(rows.parse().expect("Dispute window must be a number of rows.")));
+    let window_seconds = option_value(args, "--dispute-window-seconds")
+        .map(|seconds| DisputeWindow::Seconds(seconds.parse().expect("Dispute window must be a number of seconds.")));
+    if let Some(window) = window_rows.or(window_seconds) {
+        transactions_info.set_dispute_window(window);
+    }
+    if let Some(state) = option_value(args, "--state") {
+        if let Err(err) = load_state(Path::new(state), &mut transactions_info) {
+            exit_with_error(err);
+        }
+    }
+    if let Some(deltas) = option_value(args, "--deltas") {
+        let every = option_value(args, "--deltas-every")
+            .map(|rows| rows.parse().expect("Deltas every must be a number of rows."))
+            .unwrap_or(1);
+        match File::create(deltas) {
+            Ok(file) => transactions_info.set_deltas(DeltaStream::new(Box::new(BufWriter::new(file)), every)),
+            Err(err) => exit_with_error(err.into()),
+        }
+    }
+    transactions_info
+}
 
-    if let Err(err) = proccess_input(&mut rdr, &mut transactions_info) {
-        println!("Error: {}", err);
-        process::exit(1);
+fn run(args: &[String], transactions_info: &mut TransactionsInfo) {
+    if let Some(threads) = option_value(args, "--threads") {
+        let threads = threads.parse().expect("Threads must be a number of threads.");
+        if let Err(err) = ThreadPoolBuilder::new().num_threads(threads).build_global() {
+            exit_with_error(err.into());
+        }
+    }
+    if let Err(err) = proccess_input_file(args, transactions_info) {
+        exit_with_error(err);
+    };
+    if let Some(admin_path) = option_value(args, "--admin") {
+        let mut admin_rdr = open_csv(Path::new(admin_path));
+        if let Err(err) = proccess_admin_input(&mut admin_rdr, transactions_info) {
+            exit_with_error(err);
+        };
+    }
+    if let Err(err) = transactions_info.flush_observers() {
+        exit_with_error(err);
+    }
+    if let Err(err) = transactions_info.flush_deltas() {
+        exit_with_error(err);
+    }
+    if let Some(state) = option_value(args, "--state") {
+        if let Err(err) = save_state(Path::new(state), transactions_info) {
+            exit_with_error(err);
+        }
+    }
+    if let Some(row_index) = transactions_info.get_row_index() {
+        eprintln!("{}", row_index.get_counts());
+        if let Some(conflicts_path) = option_value(args, "--conflicts") {
+            let conflicts = Writer::from_path(conflicts_path)
+                .map_err(|err| err.into())
+                .and_then(|mut conflicts_wtr| row_index.output_conflicts(&mut conflicts_wtr));
+            if let Err(err) = conflicts {
+                exit_with_error(err);
+            }
+        }
+    }
+    if let (Some(alerts_path), Some(rules)) = (option_value(args, "--alerts"), transactions_info.get_rules()) {
+        let alerts = match option_value(args, "--alerts-format").map(|format| format.as_str()) {
+            Some("json") => File::create(alerts_path)
+                .map_err(|err| err.into())
+                .and_then(|mut alerts_file| rules.output_alerts_json(&mut alerts_file)),
+            Some("csv") | None => Writer::from_path(alerts_path)
+                .map_err(|err| err.into())
+                .and_then(|mut alerts_wtr| rules.output_alerts_csv(&mut alerts_wtr)),
+            Some(format) => Err(format!("unknown alerts format {}", format).into()),
+        };
+        if let Err(err) = alerts {
+            exit_with_error(err);
+        }
+    }
+    if let Some(audit_path) = option_value(args, "--audit") {
+        let audit = Writer::from_path(audit_path)
+            .map_err(|err| err.into())
+            .and_then(|mut audit_wtr| output_audit(&mut audit_wtr, transactions_info));
+        if let Err(err) = audit {
+            exit_with_error(err);
+        }
+    }
+    if let Some(rejects_path) = option_value(args, "--rejects") {
+        let rejects = Writer::from_path(rejects_path)
+            .map_err(|err| err.into())
+            .and_then(|mut rejects_wtr| output_rejects(&mut rejects_wtr, transactions_info.get_rejects()));
+        if let Err(err) = rejects {
+            exit_with_error(err);
+        }
+    }
+    if transactions_info.has_dispute_window() {
+        eprintln!("{}", transactions_info.memory_usage());
+    }
+    let trial_balance = transactions_info.get_journal().trial_balance();
+    if !trial_balance.is_balanced() {
+        exit_with_error(format!(
+            "journal is not balanced: debits {}, credits {}",
+            trial_balance.debits, trial_balance.credits
+        ).into());
+    }
+    if let Some(journal_path) = option_value(args, "--journal") {
+        let journal = Writer::from_path(journal_path)
+            .map_err(|err| err.into())
+            .and_then(|mut journal_wtr| transactions_info.get_journal().export(&mut journal_wtr));
+        if let Err(err) = journal {
+            exit_with_error(err);
+        }
+    }
+    match optional_value(args, "--summary") {
+        Some(Some(summary_path)) => {
+            let summary = File::create(summary_path)
+                .map_err(|err| err.into())
+                .and_then(|mut summary_file| Summary::new(transactions_info).output_json(&mut summary_file));
+            if let Err(err) = summary {
+                exit_with_error(err);
+            }
+        }
+        Some(None) => eprintln!("{}", Summary::new(transactions_info)),
+        None => (),
+    }
+}
+
+/// `<file> [--as-of-tx <id> | --as-of-line <n>]`
+fn run_accounts(args: &[String]) {
+    let mut wtr = Writer::from_writer(io::stdout());
+    let mut transactions_info = configure(args);
+    let as_of_tx = option_value(args, "--as-of-tx")
+        .map(|id| TxId::new(id.parse().expect("Transaction must be a transaction id.")));
+    let as_of_line: Option<u64> = option_value(args, "--as-of-line")
+        .map(|line| line.parse().expect("Line must be a line number."));
+    if as_of_tx.is_some() || as_of_line.is_some() {
+        transactions_info.keep_history();
+    }
+    run(args, &mut transactions_info);
+
+    let as_of = match (as_of_tx, as_of_line) {
+        (Some(tx), _) => match transactions_info.clients_as_of_tx(&tx) {
+            Some(clients) => Some(clients),
+            None => exit_with_error(format!("transaction {} is not in the input", tx).into()),
+        },
+        (None, Some(line)) => Some(transactions_info.clients_as_of_line(line)),
+        (None, None) => None,
+    };
+    let result = match as_of {
+        Some(clients) => output_clients(&mut wtr, clients.values()),
+        None => output_client_data(&mut wtr, &mut transactions_info),
+    };
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+}
+
+/// `statement <file> --clients 1,2 [--format csv|json]`
+fn run_statement(args: &[String]) {
+    let client_ids = parse_client_ids(option_value(args, "--clients").expect("Statement needs --clients."));
+    let mut transactions_info = configure(args);
+    transactions_info.keep_history();
+    run(args, &mut transactions_info);
+    let lines = client_statement(&transactions_info, &client_ids);
+    let result = match option_value(args, "--format").map(|format| format.as_str()) {
+        Some("json") => output_statement_json(&mut io::stdout(), &lines),
+        Some("csv") | None => output_statement_csv(&mut Writer::from_writer(io::stdout()), &lines),
+        Some(format) => Err(format!("unknown statement format {}", format).into()),
+    };
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+}
+
+/// `disputes <file> [--clients 1,2] [--dispute-state disputed|resolved] [--format csv|json]`
+fn run_disputes(args: &[String]) {
+    let client_ids = option_value(args, "--clients").map_or(Vec::new(), |clients| parse_client_ids(clients));
+    let state = option_value(args, "--dispute-state").map(|state| state.as_str());
+    if let Some(state) = state.filter(|state| !["disputed", "resolved"].contains(state)) {
+        exit_with_error(format!("unknown dispute state {}", state).into());
+    }
+    let mut transactions_info = configure(args);
+    run(args, &mut transactions_info);
+    let lines = open_disputes(&transactions_info, &client_ids, state);
+    let result = match option_value(args, "--format").map(|format| format.as_str()) {
+        Some("json") => output_disputes_json(&mut io::stdout(), &lines),
+        Some("csv") | None => output_disputes_csv(&mut Writer::from_writer(io::stdout()), &lines),
+        Some(format) => Err(format!("unknown disputes format {}", format).into()),
+    };
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+}
+
+/// `reconcile <before.csv> (<after.csv> | --input <file> | --replay <events.ndjson>) [--tolerance <amount>]`
+///
+/// Exits with 1 when the accounts differ, with 2 on an error.
+fn run_reconcile(args: &[String]) {
+    ERROR_CODE.store(2, Ordering::Relaxed);
+    let before = match args.first().filter(|arg| !arg.starts_with("--")) {
+        Some(before) => before,
+        None => exit_with_error("reconcile needs an accounts file".into()),
     };
-    if let Err(err) = output_client_data(&mut wtr, &mut transactions_info) {
-        println!("Error: {}", err);
+    let (input, events) = (option_value(args, "--input"), option_value(args, "--replay"));
+    let second = args.get(1).filter(|arg| !arg.starts_with("--"));
+    if second.is_none() && input.is_none() && events.is_none() {
+        exit_with_error("reconcile needs a second accounts file, --input or --replay".into());
+    }
+    let tolerance = match option_value(args, "--tolerance").map(|tolerance| (tolerance, tolerance.parse())) {
+        Some((_, Ok(tolerance))) => tolerance,
+        Some((tolerance, Err(_))) => exit_with_error(format!("invalid tolerance '{}'", tolerance).into()),
+        None => Default::default(),
+    };
+    let before = match read_accounts_file(before) {
+        Ok(before) => before,
+        Err(err) => exit_with_error(err),
+    };
+    let mut transactions_info = match (input, events) {
+        (Some(input), _) => {
+            let engine_args: Vec<String> = std::iter::once(input.clone()).chain(args[1..].iter().cloned()).collect();
+            let mut transactions_info = configure(&engine_args);
+            run(&engine_args, &mut transactions_info);
+            transactions_info
+        }
+        (None, Some(events)) => {
+            let mut transactions_info = TransactionsInfo::new();
+            let result = File::open(events).map_err(Box::from).and_then(|events| replay(BufReader::new(events), &mut transactions_info));
+            if let Err(err) = result {
+                exit_with_error(err);
+            }
+            transactions_info
+        }
+        (None, None) => TransactionsInfo::new(),
+    };
+    let after = match second {
+        Some(after) => read_accounts_file(after),
+        None => {
+            transactions_info.rescale_clients(4);
+            Ok(transactions_info.get_clients().clone())
+        }
+    };
+    let after = match after {
+        Ok(after) => after,
+        Err(err) => exit_with_error(err),
+    };
+    let differences = reconcile(&before, &after, tolerance);
+    if let Err(err) = output_differences(&mut Writer::from_writer(io::stdout()), &differences) {
+        exit_with_error(err);
+    }
+    if !differences.is_empty() {
         process::exit(1);
     }
 }
+
+fn read_accounts_file(path: &str) -> Result<HashMap<ClientID, Client>, Box<dyn Error>> {
+    read_accounts(&mut ReaderBuilder::new().trim(Trim::All).flexible(true).from_path(path)?)
+}
+
+/// `gen [--clients <n>] [--rows <n>] [--mix deposit=60,withdrawal=40] [--dispute-rate <share>]
+/// [--invalid-rate <share>] [--seed <n>] [--expected <accounts.csv>]`
+fn run_gen(args: &[String]) {
+    let defaults = GeneratorConfig::default();
+    let config = GeneratorConfig {
+        clients: option_value(args, "--clients")
+            .map_or(defaults.clients, |clients| clients.parse().expect("Clients must be a number of clients.")),
+        rows: option_value(args, "--rows").map_or(defaults.rows, |rows| rows.parse().expect("Rows must be a number of rows.")),
+        mix: option_value(args, "--mix").map_or(defaults.mix, |mix| mix.parse().unwrap_or_else(|err: TypeMixError| exit_with_error(err.into()))),
+        dispute_rate: option_value(args, "--dispute-rate")
+            .map_or(defaults.dispute_rate, |rate| rate.parse().expect("Dispute rate must be a share of the rows.")),
+        invalid_rate: option_value(args, "--invalid-rate")
+            .map_or(defaults.invalid_rate, |rate| rate.parse().expect("Invalid rate must be a share of the rows.")),
+        seed: option_value(args, "--seed").map_or(defaults.seed, |seed| seed.parse().expect("Seed must be a number.")),
+    };
+    let mut generator = Generator::new(config);
+    let mut wtr = Writer::from_writer(BufWriter::new(io::stdout()));
+    if let Err(err) = output_rows(&mut wtr, &mut generator) {
+        exit_with_error(err);
+    }
+    if let Some(expected) = option_value(args, "--expected") {
+        let file = File::create(expected).expect("Something went wrong writing the expected accounts.");
+        if let Err(err) = output_clients(&mut Writer::from_writer(file), generator.get_model().clients().iter()) {
+            exit_with_error(err);
+        }
+    }
+}
+
+/// `replay <events.ndjson>`
+fn run_replay(args: &[String]) {
+    let events = File::open(args.first().expect("Event log is missing.")).expect("Something went wrong reading the file.");
+    let mut transactions_info = TransactionsInfo::new();
+    if let Err(err) = replay(BufReader::new(events), &mut transactions_info) {
+        exit_with_error(err);
+    }
+    if let Err(err) = output_client_data(&mut Writer::from_writer(io::stdout()), &mut transactions_info) {
+        exit_with_error(err);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|command| command.as_str()) {
+        Some("statement") => run_statement(&args[2..]),
+        Some("disputes") => run_disputes(&args[2..]),
+        Some("reconcile") => run_reconcile(&args[2..]),
+        Some("replay") => run_replay(&args[2..]),
+        Some("gen") => run_gen(&args[2..]),
+        _ => run_accounts(&args[1..]),
+    }
+}
@@ -0,0 +1,338 @@
+//! A reference model of the engine, with no transfers, fees, limits or
+//! rules. It is run against `TransactionsInfo` by the property tests and
+//! gives the expected accounts of generated inputs.
+
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::transactions::{Client, ClientID, TxType};
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Row {
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let amount = self.amount.map(|amount| amount.to_string()).unwrap_or_default();
+        write!(f, "{},{},{},{}", self.tx_type.name(), self.client, self.tx, amount)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisputeState {
+    None,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+struct Stored {
+    withdrawal: bool,
+    amount: Decimal,
+    state: DisputeState,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Account {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+/// The rules of the engine written out for one client at a time: a dispute
+/// holds what is left of a deposit, a resolve releases it, and only a
+/// resolved transaction can be charged back. Charging back a deposit locks
+/// the account, charging back a withdrawal unlocks it. A locked account
+/// cannot withdraw. Transactions are kept by tx id and client, clients can
+/// reuse each other's tx ids.
+#[derive(Default)]
+pub struct Model {
+    accounts: BTreeMap<u16, Account>,
+    stored: HashMap<(u32, u16), Stored>,
+    net_inflow: Decimal,
+}
+
+impl Model {
+    pub fn new() -> Model {
+        Model::default()
+    }
+
+    pub fn apply(&mut self, row: &Row) {
+        match row.tx_type {
+            TxType::Deposit | TxType::Refund => {
+                let amount = row.amount.unwrap_or_default();
+                self.accounts.entry(row.client).or_default().available += amount;
+                self.net_inflow += amount;
+                self.store(row, false);
+            }
+            TxType::Withdrawal => {
+                let amount = row.amount.unwrap_or_default();
+                let account = self.accounts.entry(row.client).or_default();
+                if account.available >= amount && !account.locked {
+                    account.available -= amount;
+                    self.net_inflow -= amount;
+                    self.store(row, true);
+                }
+            }
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => self.follow(row),
+            TxType::Transfer | TxType::Unknown(_) => (),
+        }
+    }
+
+    fn store(&mut self, row: &Row, withdrawal: bool) {
+        let amount = row.amount.unwrap_or_default();
+        self.stored.insert((row.tx, row.client), Stored { withdrawal, amount, state: DisputeState::None });
+    }
+
+    fn follow(&mut self, row: &Row) {
+        let stored = match self.stored.get_mut(&(row.tx, row.client)) {
+            Some(stored) => stored,
+            None => return,
+        };
+        let account = self.accounts.entry(row.client).or_default();
+        match (&row.tx_type, stored.state) {
+            (TxType::Dispute, DisputeState::None) => {
+                if !stored.withdrawal {
+                    let held = stored.amount.min(account.available);
+                    account.available -= held;
+                    account.held += held;
+                }
+                stored.state = DisputeState::Disputed;
+            }
+            (TxType::Resolve, DisputeState::Disputed) => {
+                if !stored.withdrawal {
+                    let released = stored.amount.min(account.held);
+                    account.held -= released;
+                    account.available += released;
+                }
+                stored.state = DisputeState::Resolved;
+            }
+            (TxType::Chargeback, DisputeState::Resolved) => {
+                if stored.withdrawal {
+                    account.available += stored.amount;
+                    self.net_inflow += stored.amount;
+                    account.locked = false;
+                } else {
+                    let charged = stored.amount.min(account.available);
+                    account.available -= charged;
+                    self.net_inflow -= charged;
+                    account.locked = true;
+                }
+                stored.state = DisputeState::ChargedBack;
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether `row` charges back a withdrawal, the one row that unlocks an
+    /// account. Called before the row is applied.
+    pub fn unlocks(&self, row: &Row) -> bool {
+        row.tx_type == TxType::Chargeback
+            && self
+                .stored
+                .get(&(row.tx, row.client))
+                .is_some_and(|stored| stored.withdrawal && stored.state == DisputeState::Resolved)
+    }
+
+    pub fn available(&self, client: u16) -> Decimal {
+        self.accounts.get(&client).map_or(Decimal::ZERO, |account| account.available)
+    }
+
+    pub fn accounts(&self) -> &BTreeMap<u16, Account> {
+        &self.accounts
+    }
+
+    /// The accounts as the engine reports them, by client id.
+    pub fn clients(&self) -> Vec<Client> {
+        self.accounts
+            .iter()
+            .map(|(client, account)| {
+                let total = account.available + account.held;
+                Client::create_with_values(ClientID::new(*client), account.available, account.held, total, account.locked)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::ReaderBuilder;
+    use proptest::prelude::*;
+    use rust_decimal::Decimal;
+    use std::collections::{BTreeMap, HashMap};
+    use std::fmt;
+
+    use crate::model::{Account, Model, Row};
+    use crate::proccess_input_output::proccess_input;
+    use crate::transactions::{Client, ClientID, TxType};
+    use crate::transactions_info::TransactionsInfo;
+
+    const HEADER: &str = "type,client,tx,amount";
+
+    /// One generated step. A reused deposit or withdrawal takes the tx id of
+    /// one of the deposits and withdrawals of other clients before it, by
+    /// index. Disputes, resolves and chargebacks pick one of the deposits and
+    /// withdrawals before them by index.
+    #[derive(Debug, Clone)]
+    enum Step {
+        Deposit(u16, i64),
+        Withdrawal(u16, i64),
+        Reuse(TxType, u16, i64, usize),
+        Follow(TxType, usize),
+    }
+
+    /// A transaction stream, printed as the CSV input it stands for so that a
+    /// shrunk failure can be run as is.
+    #[derive(Clone)]
+    struct Stream(Vec<Row>);
+
+    impl Stream {
+        /// Deposits and withdrawals get tx ids from 1 up, unless they reuse the
+        /// tx id of another client. A reuse with no transaction of another
+        /// client to pick, or of a tx id the client already used, gets a new
+        /// one. A step that follows a transaction when there is none yet names
+        /// tx 0, which is unknown.
+        fn new(steps: Vec<Step>) -> Stream {
+            let mut stored: Vec<(u16, u32)> = Vec::new();
+            let mut next_tx = 1;
+            let mut rows = Vec::new();
+            for step in steps {
+                let row = match step {
+                    Step::Deposit(client, cents) | Step::Withdrawal(client, cents) | Step::Reuse(_, client, cents, _) => {
+                        let tx_type = match &step {
+                            Step::Deposit(..) => TxType::Deposit,
+                            Step::Reuse(tx_type, ..) => tx_type.clone(),
+                            _ => TxType::Withdrawal,
+                        };
+                        let reused = match step {
+                            Step::Reuse(_, _, _, index) if !stored.is_empty() => Some(stored[index % stored.len()].1),
+                            _ => None,
+                        };
+                        let tx = match reused {
+                            Some(tx) if !stored.contains(&(client, tx)) => tx,
+                            _ => {
+                                next_tx += 1;
+                                next_tx - 1
+                            }
+                        };
+                        stored.push((client, tx));
+                        Row { tx_type, client, tx, amount: Some(Decimal::new(cents, 2)) }
+                    }
+                    Step::Follow(tx_type, index) => {
+                        let (client, tx) = match stored.is_empty() {
+                            true => (1, 0),
+                            false => stored[index % stored.len()],
+                        };
+                        Row { tx_type, client, tx, amount: None }
+                    }
+                };
+                rows.push(row);
+            }
+            Stream(rows)
+        }
+
+        fn csv<'a>(rows: impl IntoIterator<Item = &'a Row>) -> String {
+            let mut csv = HEADER.to_string();
+            for row in rows {
+                csv.push('\n');
+                csv.push_str(&row.to_string());
+            }
+            csv
+        }
+    }
+
+    impl fmt::Debug for Stream {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f)?;
+            write!(f, "{}", Stream::csv(&self.0))
+        }
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        let client = 1u16..=4;
+        // Few distinct amounts, so that withdrawals often take exactly what is left.
+        let cents = (1i64..=20).prop_map(|quarters| quarters * 25);
+        let follow = |tx_type: TxType| any::<usize>().prop_map(move |index| Step::Follow(tx_type.clone(), index));
+        let reused = prop_oneof![Just(TxType::Deposit), Just(TxType::Withdrawal)];
+        prop_oneof![
+            4 => (client.clone(), cents.clone()).prop_map(|(client, cents)| Step::Deposit(client, cents)),
+            3 => (client.clone(), cents.clone()).prop_map(|(client, cents)| Step::Withdrawal(client, cents)),
+            2 => (reused, client, cents, any::<usize>())
+                .prop_map(|(tx_type, client, cents, index)| Step::Reuse(tx_type, client, cents, index)),
+            2 => follow(TxType::Dispute),
+            2 => follow(TxType::Resolve),
+            2 => follow(TxType::Chargeback),
+        ]
+    }
+
+    fn stream() -> impl Strategy<Value = Stream> {
+        prop::collection::vec(step(), 1..60).prop_map(Stream::new)
+    }
+
+    fn run(engine: &mut TransactionsInfo, csv: &str) {
+        let mut rdr = ReaderBuilder::new().from_reader(csv.as_bytes());
+        proccess_input(&mut rdr, engine).unwrap();
+    }
+
+    fn accounts(clients: &HashMap<ClientID, Client>) -> BTreeMap<u16, Account> {
+        clients
+            .values()
+            .map(|client| (client.client.value(), Account { available: client.available, held: client.held, locked: client.locked }))
+            .collect()
+    }
+
+    proptest! {
+        /// Runs every row through the engine and the model and checks after
+        /// each one that they agree, that funds are conserved, that no balance
+        /// is inconsistent and that only a withdrawal chargeback unlocks.
+        #[test]
+        fn engine_matches_model(stream in stream()) {
+            let mut engine = TransactionsInfo::new();
+            let mut model = Model::default();
+            for row in stream.0.iter() {
+                let before = accounts(engine.get_clients());
+                let unlocks = model.unlocks(row);
+                run(&mut engine, &Stream::csv([row]));
+                model.apply(row);
+
+                let after = accounts(engine.get_clients());
+                prop_assert_eq!(&after, model.accounts(), "after {}", row);
+                let total: Decimal = engine.get_clients().values().map(|client| client.total).sum();
+                prop_assert_eq!(total, model.net_inflow, "after {}", row);
+                prop_assert!(engine.get_journal().trial_balance().is_balanced());
+                for client in engine.get_clients().values() {
+                    prop_assert!(client.held >= Decimal::ZERO, "held of {} after {}", client.client.value(), row);
+                    prop_assert!(client.available >= Decimal::ZERO, "available of {} after {}", client.client.value(), row);
+                    prop_assert_eq!(client.available + client.held, client.total);
+                }
+                for (client, account) in before.iter().filter(|(_, account)| account.locked) {
+                    let still_locked = after.get(client).is_some_and(|account| account.locked);
+                    prop_assert!(still_locked || (unlocks && *client == row.client), "{:?} of {} unlocked by {}", account, client, row);
+                }
+            }
+        }
+
+        /// The transactions of a client are its own, even under a tx id that
+        /// another client used first, so interleaving their rows differently
+        /// gives the same accounts.
+        #[test]
+        fn client_order_does_not_matter(stream in stream()) {
+            let mut by_client = stream.0.clone();
+            by_client.sort_by_key(|row| std::cmp::Reverse(row.client));
+
+            let mut engine = TransactionsInfo::new();
+            run(&mut engine, &Stream::csv(&stream.0));
+            let mut reordered = TransactionsInfo::new();
+            run(&mut reordered, &Stream::csv(&by_client));
+            prop_assert_eq!(accounts(engine.get_clients()), accounts(reordered.get_clients()));
+        }
+    }
+}
@@ -1,29 +1,237 @@
-use csv::{Reader, Writer};
+use csv::{ByteRecord, Reader, StringRecord, Writer};
+use memmap2::Mmap;
 use std::error::Error;
+use std::fs::File;
 use std::io::{Read, Write};
+use std::panic;
+use std::thread;
 
+use crate::admin::{adjustment, authorize, close, unlock, AdminTemplate, AdminTxType};
+use crate::events::EngineEvent;
+use crate::ingest::{parse_chunks, parse_row, row_error, split_lines, CHUNK_BYTES};
+use crate::profile::{Layout, Profile};
 use crate::transactions::{
-    chargeback, deposit, dispute, resolve, withdrawal, TransactionTemplate, TxType,
+    chargeback, deposit, dispute, refund, resolve, transfer, withdrawal, Client, Outcome, Rejection,
+    TransactionTemplate, TxType,
 };
-use crate::transactions_info::TransactionsInfo;
+use crate::transactions_info::{HistoryRow, TransactionsInfo};
 
+/// Files with only the columns `type, client, tx, amount` are read with
+/// `parse_row`, other files with serde. Fields are trimmed either way, the
+/// reader does not need to trim them.
 pub fn proccess_input<R: Read>(
     rdr: &mut Reader<R>,
     transactions_info: &mut TransactionsInfo,
 ) -> Result<(), Box<dyn Error>> {
-    for result in rdr.deserialize() {
-        let transaction: TransactionTemplate = result?;
-        let result = match transaction.tx_type {
-            TxType::Deposit => deposit(transaction, transactions_info),
-            TxType::Withdrawal => withdrawal(transaction, transactions_info),
-            TxType::Dispute => dispute(transaction, transactions_info),
-            TxType::Resolve => resolve(transaction, transactions_info),
-            TxType::Chargeback => chargeback(transaction, transactions_info),
-        };
-        if result.is_err() {
-            return result
+    proccess_partner_input(rdr, &Profile::default(), transactions_info)
+}
+
+/// Like `proccess_input`, for the files of a partner. `rdr` has to be built
+/// with `Profile::reader_builder`.
+pub fn proccess_partner_input<R: Read>(
+    rdr: &mut Reader<R>,
+    profile: &Profile,
+    transactions_info: &mut TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    let layout = profile.layout(rdr.headers()?)?;
+    if !layout.is_plain() {
+        return proccess_layout(rdr, &layout, transactions_info);
+    }
+    let mut record = ByteRecord::new();
+    while rdr.read_byte_record(&mut record)? {
+        let line = record.position().map_or(0, |position| position.line());
+        let transaction = parse_row(&record, line, &profile.aliases).map_err(|err| row_error(&record, err))?;
+        proccess_row(transaction, line, transactions_info)?;
+    }
+    Ok(())
+}
+
+fn proccess_layout<R: Read>(
+    rdr: &mut Reader<R>,
+    layout: &Layout,
+    transactions_info: &mut TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    let mut record = StringRecord::new();
+    let mut row = StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        let transaction = layout.template(&record, &mut row)?;
+        let line = record.position().map_or(0, |position| position.line());
+        proccess_row(transaction, line, transactions_info)?;
+    }
+    Ok(())
+}
+
+/// Like `proccess_partner_input`, for a whole file in memory, such as a
+/// memory mapped file. Files with only the columns `type, client, tx,
+/// amount` are split at line ends and the pieces are parsed in parallel, the
+/// next pieces while the rows of the last ones are applied. The rows are
+/// applied in the order of the file, as by `proccess_input`. Files with
+/// quotes are read row by row, since a quoted field can hold a line end.
+pub fn proccess_bytes(
+    data: &[u8],
+    profile: &Profile,
+    transactions_info: &mut TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    proccess_chunks(data, profile, CHUNK_BYTES, transactions_info)
+}
+
+/// Reads a file of a partner. A regular file is mapped into memory and read
+/// by `proccess_bytes`, anything else, like a pipe or a terminal, or a file
+/// that cannot be mapped, is read row by row by `proccess_partner_input`.
+pub fn proccess_file(file: File, profile: &Profile, transactions_info: &mut TransactionsInfo) -> Result<(), Box<dyn Error>> {
+    let mapped = match file.metadata() {
+        // The file must not be changed by another process while it is mapped.
+        Ok(metadata) if metadata.is_file() => unsafe { Mmap::map(&file) }.ok(),
+        _ => None,
+    };
+    match mapped {
+        Some(data) => proccess_bytes(&data, profile, transactions_info),
+        None => proccess_partner_input(&mut profile.reader_builder().from_reader(file), profile, transactions_info),
+    }
+}
+
+fn proccess_chunks(
+    data: &[u8],
+    profile: &Profile,
+    chunk_bytes: usize,
+    transactions_info: &mut TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    let mut rdr = profile.reader_builder().from_reader(data);
+    if data.contains(&b'"') {
+        return proccess_partner_input(&mut rdr, profile, transactions_info);
+    }
+    let layout = profile.layout(rdr.headers()?)?;
+    if !layout.is_plain() {
+        return proccess_layout(&mut rdr, &layout, transactions_info);
+    }
+    let (body, first_line) = match profile.headers {
+        true => (&data[rdr.position().byte() as usize..], rdr.position().line()),
+        false => (data, 1),
+    };
+    let chunks = split_lines(body, chunk_bytes);
+    let mut waves = chunks.chunks(rayon::current_num_threads());
+    let (delimiter, aliases) = (profile.delimiter, &profile.aliases);
+    thread::scope(|scope| {
+        let mut pending = waves.next().map(|wave| scope.spawn(move || parse_chunks(wave, first_line, delimiter, aliases)));
+        while let Some(parsing) = pending {
+            let (batches, next_line) = parsing.join().unwrap_or_else(|panic| panic::resume_unwind(panic));
+            pending = waves.next().map(|wave| scope.spawn(move || parse_chunks(wave, next_line, delimiter, aliases)));
+            for batch in batches {
+                for (line, transaction) in batch.rows {
+                    proccess_row(transaction, line, transactions_info)?;
+                }
+                if let Some(err) = batch.error {
+                    return Err(first_error(data, profile).unwrap_or(err));
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// The error of the first row of `data` that cannot be read, read again row
+/// by row so that it names the position of the row in the file rather than
+/// in its piece.
+fn first_error(data: &[u8], profile: &Profile) -> Option<Box<dyn Error>> {
+    let mut rdr = profile.reader_builder().from_reader(data);
+    let mut record = ByteRecord::new();
+    loop {
+        match rdr.read_byte_record(&mut record) {
+            Ok(true) => {
+                let line = record.position().map_or(0, |position| position.line());
+                if let Err(err) = parse_row(&record, line, &profile.aliases) {
+                    return Some(row_error(&record, err));
+                }
+            }
+            Ok(false) => return None,
+            Err(err) => return Some(err.into()),
         }
     }
+}
+
+fn proccess_row(
+    transaction: TransactionTemplate,
+    line: u64,
+    transactions_info: &mut TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    transactions_info.count_row_read();
+    if !transactions_info.admit_row(line, &transaction) {
+        return Ok(());
+    }
+    transactions_info.emit(EngineEvent::RowRead { line, timestamp: transaction.get_timestamp() })?;
+    let keeps_row = transactions_info.keeps_history() || transactions_info.keeps_rejects();
+    let row = keeps_row.then(|| transaction.clone());
+    let (tx_type, client, tx, timestamp) = (
+        transaction.tx_type.clone(),
+        transaction.get_client().clone(),
+        transaction.get_tx().clone(),
+        transaction.get_timestamp(),
+    );
+    let outcome = match transaction.tx_type {
+        TxType::Deposit => deposit(transaction, transactions_info)?,
+        TxType::Withdrawal => withdrawal(transaction, transactions_info)?,
+        TxType::Dispute => dispute(transaction, transactions_info)?,
+        TxType::Resolve => resolve(transaction, transactions_info)?,
+        TxType::Chargeback => chargeback(transaction, transactions_info)?,
+        TxType::Transfer => transfer(transaction, transactions_info)?,
+        TxType::Refund => refund(transaction, transactions_info)?,
+        TxType::Unknown(ref name) => Outcome::Rejected(Rejection::UnknownType(name.clone())),
+    };
+    transactions_info.count_outcome(tx_type.name(), &outcome);
+    transactions_info.lock_by_rules(&client)?;
+    let stored = match tx_type {
+        TxType::Deposit | TxType::Withdrawal | TxType::Transfer | TxType::Refund if outcome == Outcome::Applied => {
+            Some((tx.clone(), client.clone()))
+        }
+        _ => None,
+    };
+    transactions_info.evict_expired(stored, timestamp)?;
+    transactions_info.row_done()?;
+    if let Some(row) = row {
+        if let Outcome::Rejected(rejection) = &outcome {
+            transactions_info.record_reject(line, row.clone(), rejection.clone());
+        }
+        transactions_info.record_history(line, HistoryRow::Transaction(row), outcome);
+    }
+    Ok(())
+}
+
+/// Applies the rows of the admin file. Every row has to name a reason code
+/// and an operator, otherwise processing stops with an error.
+pub fn proccess_admin_input<R: Read>(
+    rdr: &mut Reader<R>,
+    transactions_info: &mut TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    let headers = rdr.headers()?.clone();
+    let mut record = StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        let transaction: AdminTemplate = record.deserialize(Some(&headers))?;
+        let line = record.position().map_or(0, |position| position.line());
+        transactions_info.count_row_read();
+        authorize(&transaction, transactions_info)?;
+        if !transactions_info.admit_admin_row(line, &transaction) {
+            continue;
+        }
+        let outcome = match transaction.tx_type {
+            AdminTxType::Unlock => unlock(&transaction, transactions_info)?,
+            AdminTxType::Close => close(&transaction, transactions_info)?,
+            AdminTxType::Adjustment => adjustment(&transaction, transactions_info)?,
+        };
+        transactions_info.count_outcome(transaction.tx_type.name(), &outcome);
+        transactions_info.record_audit(line, transaction, outcome);
+        transactions_info.row_done()?;
+    }
+    Ok(())
+}
+
+pub fn output_audit<W: Write>(
+    wtr: &mut Writer<W>,
+    transactions_info: &TransactionsInfo,
+) -> Result<(), Box<dyn Error>> {
+    for entry in transactions_info.get_audit() {
+        wtr.serialize(entry.to_row())?;
+    }
+    wtr.flush()?;
     Ok(())
 }
 
@@ -32,8 +240,17 @@ pub fn output_client_data<W: Write>(
     transactions_info: &mut TransactionsInfo,
 ) -> Result<(), Box<dyn Error>> {
     transactions_info.rescale_clients(4);
-    let clients = transactions_info.get_clients();
-    for client in clients.values() {
+    output_clients(wtr, transactions_info.get_clients().values())
+}
+
+/// Writes `clients` in the same format as `output_client_data`.
+pub fn output_clients<'a, W: Write>(
+    wtr: &mut Writer<W>,
+    clients: impl IntoIterator<Item = &'a Client>,
+) -> Result<(), Box<dyn Error>> {
+    for client in clients {
+        let mut client = client.clone();
+        client.rescale(4);
         wtr.serialize(client)?;
     }
     wtr.flush()?;
@@ -42,12 +259,65 @@ pub fn output_client_data<W: Write>(
 
 #[cfg(test)]
 mod tests {
-    use crate::proccess_input_output::{proccess_input, output_client_data};
+    use crate::admin::{AdminTemplate, AdminTxType, Operators};
+    use crate::deltas::DeltaStream;
+    use crate::disputes::{open_disputes, DisputeLine};
+    use crate::events::{EngineEvent, EngineObserver, NdjsonEventLog};
+    use crate::fees::{FeeSchedule, FeeTier};
+    use crate::generator::{output_rows, Generator, GeneratorConfig};
+    use crate::idempotency::{admin_fingerprint, fingerprint, Conflict, RowCounts, RowIndex, RowType};
+    use crate::proccess_input_output::{
+        proccess_admin_input, proccess_chunks, proccess_file, proccess_input, proccess_partner_input, proccess_row,
+        output_client_data,
+    };
+    use crate::ingest::TypeAliases;
+    use crate::profile::Profile;
+    use crate::rejects::output_rejects;
+    use crate::projection::replay;
+    use crate::reconcile::{read_accounts, reconcile, Difference};
+    use crate::retention::DisputeWindow;
+    use crate::statement::{client_statement, output_statement_csv};
+    use crate::summary::{RowOutcomes, Summary};
     use crate::transactions_info::TransactionsInfo;
-    use crate::transactions::{Client, ClientID, TxId, Transaction};
-    use csv::{ReaderBuilder, Trim};
+    use crate::limits::{LimitKind, Limits};
+    use crate::rules::{Rule, RuleAction, RuleEngine, RuleKind};
+    use crate::transactions::{Client, ClientID, Outcome, Rejection, TransactionTemplate, TxId, TxType, Transaction, MAX_AMOUNT};
+    use csv::{ReaderBuilder, StringRecord, Trim};
+    use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
     use bytebuffer::ByteBuffer;
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::rc::Rc;
+
+
+    fn engine() -> TransactionsInfo {
+        let mut transaction_info = TransactionsInfo::new();
+        transaction_info.keep_events();
+        transaction_info
+    }
+
+    /// `into` after the events of `transaction_info` were replayed into it
+    /// from their event log.
+    fn replayed(transaction_info: &TransactionsInfo, mut into: TransactionsInfo) -> TransactionsInfo {
+        let mut buffer = Vec::new();
+        let mut event_log = NdjsonEventLog::new(&mut buffer);
+        for event in transaction_info.get_events() {
+            event_log.on_event(event).unwrap();
+        }
+        replay(buffer.as_slice(), &mut into).unwrap();
+        into
+    }
+
+    /// The state rebuilt from the events has to match the state of the
+    /// engine, so every test also holds when run via replay.
+    fn assert_replays(transaction_info: &TransactionsInfo) {
+        let replayed = replayed(transaction_info, TransactionsInfo::new());
+        assert_eq!(replayed.get_clients(), transaction_info.get_clients());
+        assert_eq!(replayed.get_tx_store(), transaction_info.get_tx_store());
+        assert_eq!(replayed.get_closed(), transaction_info.get_closed());
+        assert_eq!(replayed.get_journal().trial_balance(), transaction_info.get_journal().trial_balance());
+    }
 
     #[test]
     fn check_invalid_symbol_input() {
@@ -57,9 +327,37 @@ deposit, 1, 1, r";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn check_amount_out_of_range() {
+        // The second deposit used to overflow the balance and panic.
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 1e28
+deposit, 1, 2, 1e28";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_err());
+        assert!(transaction_info.get_clients().is_empty());
+
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 1000000000000000
+withdrawal, 1, 2, -1000000000000001";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_err());
+        assert_eq!(transaction_info.get_client(&ClientID::new(1)).map(|client| client.total), Some(MAX_AMOUNT));
     }
 
     #[test]
@@ -70,7 +368,7 @@ deposit, 1, 1,";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_err());
     }
@@ -95,13 +393,14 @@ chargeback, 2, 5,";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
         let expected_client1 = Client::create_with_values(client_1.clone(), dec!(1.5), dec!(0),dec!(1.5), true);
 
         assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client1);
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -113,7 +412,7 @@ deposit, 2, 2, 2.0";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
@@ -123,6 +422,7 @@ deposit, 2, 2, 2.0";
 
         assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
         assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -137,7 +437,7 @@ withdrawal, 2, 5, 3.0";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
@@ -147,6 +447,7 @@ withdrawal, 2, 5, 3.0";
 
         assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
         assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -163,7 +464,7 @@ dispute, 2, 5, ";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
@@ -181,6 +482,7 @@ dispute, 2, 5, ";
         assert!(transaction_info.disputes_contains_key(&expected_client_1_tx_2));
         assert_eq!(transaction_info.get_dispute(&expected_client_1_tx_2), Some(&expected_dispute_client_1_tx_2));
         assert!(!transaction_info.disputes_contains_key(&expected_client_2_tx_5));
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -199,7 +501,7 @@ resolve, 2, 5, ";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
@@ -217,6 +519,7 @@ resolve, 2, 5, ";
         assert!(transaction_info.disputes_contains_key(&expected_client_1_tx_2));
         assert_eq!(transaction_info.get_dispute(&expected_client_1_tx_2), Some(&expected_dispute_client_1_tx_2));
         assert!(!transaction_info.disputes_contains_key(&expected_client_2_tx_5));
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -237,7 +540,7 @@ chargeback, 2, 5,";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
@@ -255,6 +558,7 @@ chargeback, 2, 5,";
         assert!(transaction_info.disputes_contains_key(&expected_client_1_tx_2));
         assert_eq!(transaction_info.get_dispute(&expected_client_1_tx_2), Some(&expected_dispute_client_1_tx_2));
         assert!(!transaction_info.disputes_contains_key(&expected_client_2_tx_5));
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -269,13 +573,14 @@ chargeback, 1, 2,";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
         let expected_client1 = Client::create_with_values(client_1.clone(), dec!(3.0), dec!(0),dec!(3.0), false);
 
         assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client1);
+        assert_replays(&transaction_info);
     }
 
     #[test]
@@ -290,24 +595,1483 @@ chargeback, 1, 2,";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let mut transaction_info = engine();
         let result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(result.is_ok());
         let client_1 = ClientID::new(1);
         let expected_client1 = Client::create_with_values(client_1.clone(), dec!(3.0), dec!(0),dec!(3.0), true);
 
         assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client1);
+        assert_replays(&transaction_info);
     }
 
     #[test]
-    fn check_ouput() {
+    fn check_transfer() {
+        let data = "\
+type, client, tx, amount, to_client
+deposit, 1, 1, 3.0
+deposit, 2, 2, 1.0
+transfer, 1, 3, 2.0, 2
+transfer, 2, 4, 5.0, 1
+transfer, 1, 5, 1.0,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_err());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(1), dec!(0), dec!(1), false);
+        let expected_client_2 = Client::create_with_values(client_2.clone(), dec!(3), dec!(0), dec!(3), false);
+
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert!(transaction_info.transactions_contains_key(&(TxId::new(3), client_1.clone())));
+        assert!(!transaction_info.transactions_contains_key(&(TxId::new(4), client_2.clone())));
+    }
+
+    #[test]
+    fn check_transfer_locked() {
+        let data = "\
+type, client, tx, amount, to_client
+deposit, 1, 1, 3.0
+deposit, 2, 2, 1.0
+dispute, 2, 2,
+resolve, 2, 2,
+chargeback, 2, 2,
+transfer, 1, 3, 2.0, 2";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(3), dec!(0), dec!(3), false);
+        let expected_client_2 = Client::create_with_values(client_2.clone(), dec!(0), dec!(0), dec!(0), true);
+
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_transfer_chargeback() {
+        let data = "\
+type, client, tx, amount, to_client
+deposit, 1, 1, 3.0
+transfer, 1, 2, 2.0, 2
+dispute, 1, 2,
+dispute, 2, 2,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let tx_2 = (TxId::new(2), client_1.clone());
+        let expected_client_2 = Client::create_with_values(client_2.clone(), dec!(0), dec!(2), dec!(2), false);
+
+        assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_eq!(transaction_info.get_dispute(&tx_2), Some(&Transaction::Dispute));
+        assert!(!transaction_info.disputes_contains_key(&(TxId::new(2), client_2.clone())));
+
         let data = "\
 type, client, tx, amount
-deposit, 1, 1, 3.9876543";
+resolve, 1, 2,
+chargeback, 1, 2,";
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .from_reader(data.as_bytes());
-        let mut transaction_info = TransactionsInfo::new();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(3), dec!(0), dec!(3), false);
+        let expected_client_2 = Client::create_with_values(client_2.clone(), dec!(0), dec!(0), dec!(0), false);
+
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_eq!(transaction_info.get_dispute(&tx_2), Some(&Transaction::Chargeback));
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_tx_id_reused_by_client() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 5
+dispute, 1, 1, 
+deposit, 2, 1, 3
+resolve, 1, 1, ";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(5), dec!(0), dec!(5), false);
+        let expected_client_2 = Client::create_with_values(client_2.clone(), dec!(3), dec!(0), dec!(3), false);
+
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_eq!(transaction_info.get_dispute(&(TxId::new(1), client_1)), Some(&Transaction::Resolve));
+        assert_eq!(transaction_info.get_dispute(&(TxId::new(1), client_2)), None);
+        assert_replays(&transaction_info);
+    }
+
+    fn fee_schedule() -> FeeSchedule {
+        let data = "\
+type, from, flat, percent, min, max
+deposit, , , 10, ,
+withdrawal, , 0.5, , ,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let tiers: Vec<FeeTier> = rdr.deserialize().map(|tier| tier.unwrap()).collect();
+        FeeSchedule::new(ClientID::new(0), tiers)
+    }
+
+    #[test]
+    fn check_fees() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 1.0
+withdrawal, 1, 3, 1.5";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_fee_schedule(fee_schedule());
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let house = ClientID::new(0);
+        let client_1 = ClientID::new(1);
+        let expected_house = Client::create_with_values(house.clone(), dec!(0.8), dec!(0), dec!(0.8), false);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(1.2), dec!(0), dec!(1.2), false);
+
+        assert_eq!(transaction_info.get_client(&house).unwrap(), &expected_house);
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_fees_negative_amount() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, -5
+withdrawal, 1, 2, -3";
+        for fees in [None, Some(fee_schedule())] {
+            let mut rdr = ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(data.as_bytes());
+            let mut transaction_info = engine();
+            if let Some(fees) = fees {
+                transaction_info.set_fee_schedule(fees);
+            }
+            let result = proccess_input(&mut rdr, &mut transaction_info);
+            assert_eq!(result.map_err(|err| err.to_string()), Ok(()));
+            let client_1 = ClientID::new(1);
+            let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(-5), dec!(0), dec!(-5), false);
+
+            assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+            assert!(transaction_info.get_journal().trial_balance().is_balanced());
+            assert_replays(&transaction_info);
+        }
+    }
+
+    #[test]
+    fn check_fees_chargeback() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+deposit, 2, 2, 2.0
+withdrawal, 2, 3, 1.0
+dispute, 1, 1,
+dispute, 2, 3,
+resolve, 1, 1,
+resolve, 2, 3,
+chargeback, 1, 1,
+chargeback, 2, 3,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_fee_schedule(fee_schedule());
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let house = ClientID::new(0);
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let expected_house = Client::create_with_values(house.clone(), dec!(0.2), dec!(0), dec!(0.2), false);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(0), dec!(0), dec!(0), true);
+        let expected_client_2 = Client::create_with_values(client_2.clone(), dec!(1.8), dec!(0), dec!(1.8), false);
+
+        assert_eq!(transaction_info.get_client(&house).unwrap(), &expected_house);
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(transaction_info.get_client(&client_2).unwrap(), &expected_client_2);
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_journal() {
+        let data = "\
+type, client, tx, amount, to_client
+deposit, 1, 1, 3.0
+deposit, 1, 2, 1.0
+deposit, 2, 3, 2.0
+withdrawal, 1, 4, 1.5
+transfer, 2, 5, 1.0, 3
+dispute, 1, 2,
+dispute, 1, 4,
+dispute, 2, 5,
+resolve, 1, 2,
+resolve, 1, 4,
+chargeback, 1, 2,
+chargeback, 1, 4,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_fee_schedule(fee_schedule());
+        transaction_info.keep_journal();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let journal = transaction_info.get_journal();
+        let balances = journal.client_balances();
+
+        assert!(journal.trial_balance().is_balanced());
+        assert_eq!(journal.get_entries().len(), 10);
+        assert_eq!(balances.len(), transaction_info.get_clients().len());
+        for (client_id, client) in transaction_info.get_clients() {
+            let derived = balances.get(client_id).unwrap();
+            assert_eq!(derived.available, client.available);
+            assert_eq!(derived.held, client.held);
+            assert_eq!(derived.total, client.total);
+        }
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_statement() {
+        let data = "\
+type, client, tx, amount, to_client
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 5.0
+transfer, 1, 3, 1.0, 2
+dispute, 1, 3,
+dispute, 2, 7,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.keep_history();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let lines = client_statement(&transaction_info, &[client_2.clone(), client_1.clone()]);
+        let summary: Vec<(u16, u64, &str, Decimal, Decimal)> = lines
+            .iter()
+            .map(|line| (if line.client == client_1 { 1 } else { 2 }, line.line, line.outcome, line.available, line.held))
+            .collect();
+        let reasons: Vec<Option<String>> = lines.into_iter().map(|line| line.reason).collect();
+
+        assert_eq!(summary, vec![
+            (2, 4, "applied", dec!(1), dec!(0)),
+            (2, 5, "applied", dec!(0), dec!(1)),
+            (2, 6, "rejected", dec!(0), dec!(1)),
+            (1, 2, "applied", dec!(3), dec!(0)),
+            (1, 3, "rejected", dec!(3), dec!(0)),
+            (1, 4, "applied", dec!(2), dec!(0)),
+            (1, 5, "applied", dec!(2), dec!(0)),
+        ]);
+        assert_eq!(reasons[2], Some("unknown transaction".to_string()));
+        assert_eq!(reasons[4], Some("insufficient funds".to_string()));
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_as_of() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+deposit, 2, 2, 2.0
+withdrawal, 1, 3, 1.0
+dispute, 1, 1,
+resolve, 1, 1,
+chargeback, 1, 1,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.keep_history();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let as_of_tx_3 = transaction_info.clients_as_of_tx(&TxId::new(3)).unwrap();
+        let as_of_line_6 = transaction_info.clients_as_of_line(6);
+        let as_of_line_8 = transaction_info.clients_as_of_line(8);
+
+        assert_eq!(transaction_info.line_of_tx(&TxId::new(1)), Some(2));
+        assert_eq!(as_of_tx_3.get(&client_1).unwrap(), &Client::create_with_values(client_1.clone(), dec!(3), dec!(0), dec!(3), false));
+        assert_eq!(as_of_tx_3.get(&client_2).unwrap(), &Client::create_with_values(client_2.clone(), dec!(2), dec!(0), dec!(2), false));
+        assert_eq!(as_of_line_6.get(&client_1).unwrap(), &Client::create_with_values(client_1.clone(), dec!(0), dec!(2), dec!(2), false));
+        assert_eq!(as_of_line_8.get(&client_1), transaction_info.get_client(&client_1));
+        assert!(transaction_info.clients_as_of_line(2).is_empty());
+        assert!(transaction_info.clients_as_of_tx(&TxId::new(9)).is_none());
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_admin() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+deposit, 1, 2, 1.0
+deposit, 2, 3, 2.0
+withdrawal, 2, 4, 2.0
+dispute, 1, 2,
+resolve, 1, 2,
+chargeback, 1, 2,";
+        let admin_data = "\
+type, client, tx, amount, reason, operator
+unlock, 1, 100, , CB_REVIEWED, alice
+unlock, 1, 101, , CB_REVIEWED, alice
+adjustment, 1, 102, 0.5, GOODWILL, bob
+adjustment, 1, 103, -10, FIX, bob
+close, 1, 104, , CLIENT_REQUEST, alice
+close, 2, 105, , CLIENT_REQUEST, alice";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.keep_history();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let mut admin_rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(admin_data.as_bytes());
+        let result = proccess_admin_input(&mut admin_rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let client_2 = ClientID::new(2);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(3.5), dec!(0), dec!(3.5), false);
+        let outcomes: Vec<bool> = transaction_info
+            .get_audit()
+            .iter()
+            .map(|entry| entry.outcome == Outcome::Applied)
+            .collect();
+
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(outcomes, vec![true, false, true, false, false, true]);
+        assert!(transaction_info.is_closed(&client_2));
+        let lines = client_statement(&transaction_info, std::slice::from_ref(&client_1));
+        assert_eq!(lines.last().unwrap().tx_type, "close");
+        assert_eq!(lines.last().unwrap().operator, Some("alice".to_string()));
+
+        let data = "\
+type, client, tx, amount
+deposit, 2, 5, 1.0";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        assert_eq!(transaction_info.get_client(&client_2).unwrap().total, dec!(0));
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_admin_missing_operator() {
+        let admin_data = "\
+type, client, tx, amount, reason, operator
+adjustment, 1, 100, 1.0, GOODWILL,";
+        let mut admin_rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(admin_data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_admin_input(&mut admin_rdr, &mut transaction_info);
+        assert!(result.is_err());
+        assert!(transaction_info.get_client(&ClientID::new(1)).is_none());
+    }
+
+    #[test]
+    fn check_admin_operators() {
+        let admin_data = "\
+type, client, tx, amount, reason, operator
+adjustment, 1, 100, -1.0, FIX, alice
+adjustment, 1, 101, 1.0, GOODWILL, mallory";
+        let mut transaction_info = engine();
+        transaction_info.set_operators(Operators::new(["alice".to_string()]));
+        let mut admin_rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(admin_data.as_bytes());
+        let result = proccess_admin_input(&mut admin_rdr, &mut transaction_info);
+        assert_eq!(result.map_err(|err| err.to_string()), Err("operator mallory is not allowed".to_string()));
+        // The adjustment of alice is rejected before an account is opened.
+        assert!(transaction_info.get_client(&ClientID::new(1)).is_none());
+        assert_eq!(transaction_info.get_audit()[0].outcome, Outcome::Rejected(Rejection::InsufficientFunds));
+    }
+
+    #[test]
+    fn check_admin_idempotency() {
+        let admin_data = "\
+type, client, tx, amount, reason, operator
+adjustment, 1, 100, 1.0, GOODWILL, alice
+adjustment, 1, 101, 0.5, GOODWILL, alice";
+        let run = |data: &str, transaction_info: &mut TransactionsInfo| {
+            let mut admin_rdr = ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(data.as_bytes());
+            assert!(proccess_admin_input(&mut admin_rdr, transaction_info).is_ok());
+        };
+        let mut transaction_info = engine();
+        transaction_info.set_row_index(RowIndex::new());
+        run(admin_data, &mut transaction_info);
+        let mut buffer = ByteBuffer::new();
+        {
+            let mut wtr = csv::Writer::from_writer(&mut buffer);
+            assert!(transaction_info.get_row_index().unwrap().save(&mut wtr).is_ok());
+        }
+
+        let saved_rows = buffer.to_bytes();
+        let mut index_rdr = ReaderBuilder::new().from_reader(saved_rows.as_slice());
+        transaction_info.set_row_index(RowIndex::load(&mut index_rdr).unwrap());
+        run(&admin_data.replace("0.5, GOODWILL", "0.7, GOODWILL"), &mut transaction_info);
+        let row_index = transaction_info.get_row_index().unwrap();
+
+        assert_eq!(row_index.get_counts(), RowCounts { new: 0, duplicate: 1, conflicting: 1 });
+        assert_eq!(row_index.get_conflicts(), &[Conflict {
+            line: 3,
+            tx_type: RowType::Admin(AdminTxType::Adjustment),
+            client: ClientID::new(1),
+            tx: TxId::new(101),
+        }]);
+        assert_eq!(transaction_info.get_client(&ClientID::new(1)).unwrap().available, dec!(1.5));
+        assert_eq!(transaction_info.get_audit().len(), 2);
+
+        // An unknown type of the transactions file is not an admin row of the
+        // same name.
+        let data = "\
+type, client, tx, amount
+adjustment, 1, 100, 1.0";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        assert!(proccess_input(&mut rdr, &mut transaction_info).is_ok());
+        assert_eq!(transaction_info.get_row_index().unwrap().get_counts().new, 1);
+
+        // Saved indexes are only useful while fingerprints do not change.
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "reason", "operator"]);
+        let record = StringRecord::from(vec!["adjustment", "1", "100", "1.0", "GOODWILL", "alice"]);
+        let row: AdminTemplate = record.deserialize(Some(&headers)).unwrap();
+        assert_eq!(admin_fingerprint(&row), 0xd76621f1430ed3b2);
+    }
+
+    #[test]
+    fn check_limits() {
+        let data = "\
+type, client, tx, amount, to_client, timestamp
+deposit, 1, 1, 600.0, , 0
+deposit, 1, 2, 400.0, , 0
+withdrawal, 1, 3, 150.0, , 100
+withdrawal, 1, 4, 50.0, , 200
+withdrawal, 1, 5, 60.0, , 300
+withdrawal, 1, 6, 10.0, , 400
+withdrawal, 1, 7, 5.0, , 500
+withdrawal, 1, 8, 1.0, , 600
+withdrawal, 1, 9, 60.0, , 86400
+deposit, 1, 10, 600.0, , 86400";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let limits = Limits {
+            max_withdrawal: Some(dec!(100)),
+            max_daily_withdrawal_count: Some(3),
+            max_daily_withdrawal_volume: Some(dec!(115)),
+            max_new_account_deposit: Some(dec!(500)),
+            new_account_days: 1,
+            rows_per_day: None,
+        };
+        let mut transaction_info = engine();
+        transaction_info.set_limits(limits.clone());
+        transaction_info.keep_history();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let outcomes: Vec<Outcome> = transaction_info
+            .get_history()
+            .iter()
+            .map(|entry| entry.outcome.clone())
+            .collect();
+        let client_1 = ClientID::new(1);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(825), dec!(0), dec!(825), false);
+
+        assert_eq!(outcomes, vec![
+            Outcome::Rejected(Rejection::LimitExceeded(LimitKind::MaxNewAccountDeposit)),
+            Outcome::Applied,
+            Outcome::Rejected(Rejection::LimitExceeded(LimitKind::MaxWithdrawal)),
+            Outcome::Applied,
+            Outcome::Applied,
+            Outcome::Rejected(Rejection::LimitExceeded(LimitKind::MaxDailyWithdrawalVolume)),
+            Outcome::Applied,
+            Outcome::Rejected(Rejection::LimitExceeded(LimitKind::MaxDailyWithdrawalCount)),
+            Outcome::Applied,
+            Outcome::Applied,
+        ]);
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_eq!(transaction_info.get_limit_counters(&client_1).unwrap().withdrawal_count, 1);
+        assert_replays(&transaction_info);
+
+        // With the same limits, the counters are rebuilt from the events too.
+        let mut with_limits = TransactionsInfo::new();
+        with_limits.set_limits(limits);
+        let replayed = replayed(&transaction_info, with_limits);
+        assert_eq!(replayed.get_limit_counters(&client_1), transaction_info.get_limit_counters(&client_1));
+    }
+
+    #[test]
+    fn check_limits_by_rows() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 10.0
+withdrawal, 1, 2, 1.0
+withdrawal, 1, 3, 1.0
+withdrawal, 1, 4, 1.0";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let limits = Limits {
+            max_daily_withdrawal_count: Some(1),
+            new_account_days: 1,
+            rows_per_day: Some(2),
+            ..Limits::default()
+        };
+        let mut transaction_info = engine();
+        transaction_info.set_limits(limits.clone());
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(8), dec!(0), dec!(8), false);
+
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert_replays(&transaction_info);
+        let mut with_limits = TransactionsInfo::new();
+        with_limits.set_limits(limits);
+        let replayed = replayed(&transaction_info, with_limits);
+        assert_eq!(replayed.get_limit_counters(&client_1), transaction_info.get_limit_counters(&client_1));
+    }
+
+    #[test]
+    fn check_rules() {
+        let rules = "\
+rule, threshold, window, action
+dispute_ratio, 0.5, , alert
+chargeback_count, 1, , lock
+withdrawal_after_large_deposit, 100, 2, alert";
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 150.0
+deposit, 2, 2, 10.0
+withdrawal, 1, 3, 20.0
+withdrawal, 1, 4, 20.0
+dispute, 2, 2,
+resolve, 2, 2,
+chargeback, 2, 2,
+deposit, 3, 5, 10.0
+withdrawal, 3, 6, 1.0
+deposit, 4, 7, 500.0";
+        let mut rules_rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(rules.as_bytes());
+        let rules: Vec<Rule> = rules_rdr.deserialize().map(|rule| rule.unwrap()).collect();
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_rules(RuleEngine::new(rules.clone()));
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let alerts: Vec<(u64, RuleKind, RuleAction)> = transaction_info
+            .get_rules()
+            .unwrap()
+            .get_alerts()
+            .iter()
+            .map(|alert| (alert.line, alert.rule.clone(), alert.action.clone()))
+            .collect();
+        let client_1 = ClientID::new(1);
+        let expected_client_1 = Client::create_with_values(client_1.clone(), dec!(110), dec!(0), dec!(110), false);
+
+        assert_eq!(alerts, vec![
+            (4, RuleKind::WithdrawalAfterLargeDeposit, RuleAction::Alert),
+            (6, RuleKind::DisputeRatio, RuleAction::Alert),
+            (8, RuleKind::ChargebackCount, RuleAction::Lock),
+        ]);
+        assert_eq!(transaction_info.get_client(&client_1).unwrap(), &expected_client_1);
+        assert!(transaction_info.get_client(&ClientID::new(2)).unwrap().locked);
+        assert_replays(&transaction_info);
+        let mut with_rules = TransactionsInfo::new();
+        with_rules.set_rules(RuleEngine::new(rules));
+        let replayed = replayed(&transaction_info, with_rules);
+        assert_eq!(replayed.get_rules().unwrap().get_alerts(), transaction_info.get_rules().unwrap().get_alerts());
+
+        // A second file, like the next run with --state, starts again from
+        // line 2, but the window counts the rows of both.
+        let second = "\
+type, client, tx, amount
+withdrawal, 4, 8, 1.0
+deposit, 5, 9, 1.0
+deposit, 5, 10, 1.0
+withdrawal, 4, 11, 1.0";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(second.as_bytes());
+        assert!(proccess_input(&mut rdr, &mut transaction_info).is_ok());
+        let alerts = transaction_info.get_rules().unwrap().get_alerts();
+        let last_alert = alerts.last().map(|alert| (alert.line, alert.rule.clone()));
+        assert_eq!((alerts.len(), last_alert), (4, Some((2, RuleKind::WithdrawalAfterLargeDeposit))));
+    }
+
+    #[test]
+    fn check_rule_lock() {
+        let rules = vec![Rule { rule: RuleKind::DisputeRatio, threshold: dec!(0.4), window: None, action: RuleAction::Lock }];
+        let data = "\
+type, client, tx, amount, to_client
+deposit, 1, 1, 10.0,
+withdrawal, 1, 2, 4.0,
+dispute, 1, 2,,
+resolve, 1, 2,,
+chargeback, 1, 2,,
+withdrawal, 1, 3, 1.0,
+transfer, 1, 4, 1.0, 2
+deposit, 1, 5, 1.0,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_rules(RuleEngine::new(rules));
+        transaction_info.keep_history();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let outcomes: Vec<Outcome> = transaction_info.get_history().iter().map(|entry| entry.outcome.clone()).collect();
+
+        // The chargeback of a withdrawal does not lift the lock of a rule,
+        // and a locked account can neither withdraw nor transfer.
+        assert_eq!(outcomes[5..], [
+            Outcome::Rejected(Rejection::AccountLocked),
+            Outcome::Rejected(Rejection::AccountLocked),
+            Outcome::Applied,
+        ]);
+        assert!(transaction_info.is_locked_by_rule(&client_1));
+        assert_eq!(
+            transaction_info.get_client(&client_1).unwrap(),
+            &Client::create_with_values(client_1.clone(), dec!(11.0), dec!(0), dec!(11.0), true)
+        );
+        let locks: Vec<&EngineEvent> = transaction_info
+            .get_events()
+            .iter()
+            .filter(|event| matches!(event, EngineEvent::Locked { .. } | EngineEvent::Unlocked { .. }))
+            .collect();
+        assert_eq!(locks, [&EngineEvent::Locked { client: client_1.clone(), rule: Some(RuleKind::DisputeRatio) }]);
+        assert_replays(&transaction_info);
+        assert!(replayed(&transaction_info, TransactionsInfo::new()).is_locked_by_rule(&client_1));
+
+        // Only an admin unlock lifts it.
+        let admin = "\
+type, client, tx, amount, reason, operator
+unlock, 1, 6, , cleared, ops";
+        let result = proccess_admin_input(&mut ReaderBuilder::new().trim(Trim::All).from_reader(admin.as_bytes()), &mut transaction_info);
+        assert!(result.is_ok());
+        assert!(!transaction_info.is_locked(&client_1));
+        assert!(!transaction_info.is_locked_by_rule(&client_1));
+    }
+
+    struct EventRecorder {
+        events: Rc<RefCell<Vec<EngineEvent>>>,
+    }
+
+    impl EngineObserver for EventRecorder {
+        fn on_event(&mut self, event: &EngineEvent) -> Result<(), Box<dyn Error>> {
+            self.events.borrow_mut().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_events() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+deposit, 1, 2, 1.0
+withdrawal, 1, 3, 5.0
+dispute, 1, 2,
+resolve, 1, 2,
+chargeback, 1, 2,
+dispute, 1, 9,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut transaction_info = engine();
+        transaction_info.register_observer(Box::new(EventRecorder { events: events.clone() }));
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+
+        let row = |line| EngineEvent::RowRead { line, timestamp: None };
+
+        assert_eq!(*events.borrow(), vec![
+            row(2),
+            EngineEvent::AccountOpened { client: client_1.clone() },
+            EngineEvent::Deposited { client: client_1.clone(), tx: TxId::new(1), amount: dec!(3.0), fee: dec!(0), house: None },
+            row(3),
+            EngineEvent::Deposited { client: client_1.clone(), tx: TxId::new(2), amount: dec!(1.0), fee: dec!(0), house: None },
+            row(4),
+            EngineEvent::WithdrawalRejected {
+                client: client_1.clone(),
+                tx: TxId::new(3),
+                amount: dec!(5.0),
+                reason: "insufficient funds".to_string(),
+            },
+            row(5),
+            EngineEvent::Disputed { client: client_1.clone(), tx: TxId::new(2), held: dec!(1.0) },
+            row(6),
+            EngineEvent::Resolved { client: client_1.clone(), tx: TxId::new(2), released: dec!(1.0) },
+            row(7),
+            EngineEvent::ChargedBack { client: client_1.clone(), tx: TxId::new(2), amount: dec!(1.0), house: None },
+            EngineEvent::Locked { client: client_1.clone(), rule: None },
+            row(8),
+        ]);
+        assert_replays(&transaction_info);
+    }
+
+    #[test]
+    fn check_event_log() {
+        let mut buffer = ByteBuffer::new();
+        {
+            let mut event_log = NdjsonEventLog::new(&mut buffer);
+            let event = EngineEvent::Disputed { client: ClientID::new(1), tx: TxId::new(2), held: dec!(1.5) };
+            assert!(event_log.on_event(&event).is_ok());
+            assert!(event_log.on_event(&EngineEvent::Locked { client: ClientID::new(1), rule: None }).is_ok());
+        }
+        let expected_output = "\
+{\"event\":\"Disputed\",\"client\":1,\"tx\":2,\"held\":\"1.5\"}
+{\"event\":\"Locked\",\"client\":1}
+";
+        assert_eq!(String::from_utf8(buffer.to_bytes()).unwrap(), expected_output);
+    }
+
+    #[test]
+    fn check_replay() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 1.0
+dispute, 1, 2,
+resolve, 1, 2,
+chargeback, 1, 2,
+deposit, 2, 3, 2.0
+dispute, 2, 3,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let mut event_log = String::new();
+        for event in transaction_info.get_events() {
+            event_log.push_str(&serde_json::to_string(event).unwrap());
+            event_log.push('\n');
+        }
+
+        let mut replayed = TransactionsInfo::new();
+        let result = replay(event_log.as_bytes(), &mut replayed);
+        assert!(result.is_ok());
+        assert_eq!(replayed.get_clients(), transaction_info.get_clients());
+        assert_eq!(replayed.get_tx_store(), transaction_info.get_tx_store());
+        assert_eq!(
+            replayed.get_client(&ClientID::new(1)).unwrap(),
+            &Client::create_with_values(ClientID::new(1), dec!(3.0), dec!(0), dec!(3.0), false)
+        );
+        assert!(replay("{\"event\":\"Unknown\"}".as_bytes(), &mut replayed).is_err());
+    }
+
+    #[test]
+    fn check_idempotency() {
+        let first_delivery = "\
+type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, 2.0";
+        let second_delivery = "\
+type, client, tx, amount
+deposit, 1, 2, 2
+deposit, 1, 3, 5.0
+deposit, 2, 1, 9.0
+deposit, 1, 3, 5.0
+deposit, 1, 2, 3.0
+dispute, 1, 1,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(first_delivery.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_row_index(RowIndex::new());
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let mut buffer = ByteBuffer::new();
+        {
+            let mut wtr = csv::Writer::from_writer(&mut buffer);
+            assert!(transaction_info.get_row_index().unwrap().save(&mut wtr).is_ok());
+        }
+
+        let saved_rows = buffer.to_bytes();
+        let mut index_rdr = ReaderBuilder::new().from_reader(saved_rows.as_slice());
+        transaction_info.set_row_index(RowIndex::load(&mut index_rdr).unwrap());
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(second_delivery.as_bytes());
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let row_index = transaction_info.get_row_index().unwrap();
+
+        assert_eq!(row_index.get_counts(), RowCounts { new: 3, duplicate: 2, conflicting: 1 });
+        assert_eq!(row_index.get_conflicts(), &[Conflict {
+            line: 6,
+            tx_type: RowType::Transaction(TxType::Deposit),
+            client: ClientID::new(1),
+            tx: TxId::new(2),
+        }]);
+        assert_eq!(
+            transaction_info.get_client(&client_1).unwrap(),
+            &Client::create_with_values(client_1.clone(), dec!(7.0), dec!(1.0), dec!(8.0), false)
+        );
+        // Client 2 reuses the tx id of a deposit of client 1, which is not
+        // the same row.
+        assert_eq!(
+            transaction_info.get_client(&ClientID::new(2)).unwrap(),
+            &Client::create_with_values(ClientID::new(2), dec!(9.0), dec!(0), dec!(9.0), false)
+        );
+        assert_replays(&transaction_info);
+
+        // Saved indexes are only useful while fingerprints do not change.
+        let row = TransactionTemplate::new(TxType::Deposit, client_1, TxId::new(2), Some(dec!(2.00)));
+        assert_eq!(fingerprint(&row), 0x7848dfda9fc2cbd6);
+    }
+
+    #[test]
+    fn check_dispute_window() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, 2.0
+dispute, 1, 2,
+deposit, 1, 3, 3.0
+deposit, 1, 4, 4.0
+dispute, 1, 1,
+resolve, 1, 2,
+dispute, 1, 3,
+dispute, 1, 9,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        transaction_info.set_dispute_window(DisputeWindow::Rows(1));
+        transaction_info.keep_history();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let client_1 = ClientID::new(1);
+        let rejections: Vec<Option<Rejection>> = transaction_info
+            .get_history()
+            .iter()
+            .map(|entry| match &entry.outcome {
+                Outcome::Applied => None,
+                Outcome::Rejected(rejection) => Some(rejection.clone()),
+            })
+            .collect();
+
+        assert_eq!(rejections[5], Some(Rejection::DisputeWindowExpired));
+        assert_eq!(rejections[6], None);
+        assert_eq!(rejections[7], None);
+        assert_eq!(rejections[8], Some(Rejection::UnknownTransaction));
+        assert!(transaction_info.is_evicted(&(TxId::new(1), client_1.clone())));
+        assert!(!transaction_info.is_evicted(&(TxId::new(2), client_1.clone())));
+        assert_eq!(transaction_info.memory_usage().transactions, 3);
+        assert_eq!(
+            transaction_info.get_client(&client_1).unwrap(),
+            &Client::create_with_values(client_1.clone(), dec!(7.0), dec!(3.0), dec!(10.0), false)
+        );
+        assert_replays(&transaction_info);
+
+        // Evicted transactions give their memory back, only their ids are kept.
+        let deposits: String = (1..=20000).map(|tx| format!("deposit, {}, {}, 1.0\n", tx % 7, tx)).collect();
+        let run = |window: Option<DisputeWindow>| {
+            let data = format!("type, client, tx, amount\n{}", deposits);
+            let mut transaction_info = TransactionsInfo::new();
+            if let Some(window) = window {
+                transaction_info.set_dispute_window(window);
+            }
+            assert!(proccess_input(&mut ReaderBuilder::new().trim(Trim::All).from_reader(data.as_bytes()), &mut transaction_info).is_ok());
+            transaction_info.memory_usage()
+        };
+        let (kept, windowed) = (run(None), run(Some(DisputeWindow::Rows(100))));
+        assert_eq!((kept.transactions, kept.evicted), (20000, 0));
+        assert_eq!((windowed.transactions, windowed.evicted), (101, 19899));
+        assert!(windowed.bytes < kept.bytes / 4, "{} {}", windowed, kept);
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_deltas() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+deposit, 2, 2, 2.0
+withdrawal, 1, 3, 1.0
+withdrawal, 2, 4, 5.0
+dispute, 1, 1,";
+        let run = |every: u64| {
+            let mut rdr = ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(data.as_bytes());
+            let buffer = SharedBuffer::default();
+            let mut transaction_info = engine();
+            transaction_info.set_deltas(DeltaStream::new(Box::new(buffer.clone()), every));
+            assert!(proccess_input(&mut rdr, &mut transaction_info).is_ok());
+            assert!(transaction_info.flush_deltas().is_ok());
+            let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+            output
+        };
+
+        let expected_output = "\
+seq,client,available,held,total,locked
+1,1,3.0000,0.0000,3.0000,false
+2,2,2.0000,0.0000,2.0000,false
+3,1,2.0000,0.0000,2.0000,false
+4,1,0.0000,2.0000,2.0000,false
+";
+        assert_eq!(run(1), expected_output);
+        let expected_output = "\
+seq,client,available,held,total,locked
+1,1,2.0000,0.0000,2.0000,false
+2,2,2.0000,0.0000,2.0000,false
+3,1,0.0000,2.0000,2.0000,false
+";
+        assert_eq!(run(3), expected_output);
+    }
+
+    #[test]
+    fn check_summary() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+deposit, 2, 2, 2.0
+withdrawal, 1, 3, 1.0
+withdrawal, 2, 4, 5.0
+dispute, 1, 1,
+dispute, 2, 2,
+resolve, 2, 2,
+chargeback, 2, 2,
+dispute, 1, 9,
+refund, 1, 5, 0.5";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let summary = Summary::new(&transaction_info);
+
+        assert_eq!(summary.rows_read, 10);
+        assert_eq!(summary.rows.get("deposit"), Some(&RowOutcomes { applied: 2, rejected: 0 }));
+        assert_eq!(summary.rows.get("withdrawal"), Some(&RowOutcomes { applied: 1, rejected: 1 }));
+        assert_eq!(summary.rows.get("dispute"), Some(&RowOutcomes { applied: 2, rejected: 1 }));
+        assert_eq!(summary.rows.get("transfer"), None);
+        assert_eq!(summary.deposited, dec!(5.0));
+        assert_eq!(summary.refunded, dec!(0.5));
+        assert_eq!(summary.withdrawn, dec!(1.0));
+        assert_eq!(summary.held, dec!(2.0));
+        assert_eq!(summary.charged_back, dec!(2.0));
+        assert_eq!(summary.locked_accounts, 1);
+        assert_eq!(summary.open_disputes, 1);
+    }
+
+    #[test]
+    fn check_disputes_report() {
+        let data = "\
+type, client, tx, amount, timestamp
+deposit, 1, 1, 5.0, 100
+deposit, 2, 2, 3.0, 200
+withdrawal, 1, 3, 4.0, 300
+dispute, 1, 1,, 400
+dispute, 2, 2,, 500
+resolve, 2, 2,, 600
+deposit, 1, 4, 2.0, 700
+dispute, 1, 4,, 800
+resolve, 1, 4,, 900
+chargeback, 1, 4,, 1000";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let (client_1, client_2) = (ClientID::new(1), ClientID::new(2));
+
+        let disputes = open_disputes(&transaction_info, &[], None);
+        assert_eq!(disputes, vec![
+            DisputeLine {
+                client: client_1.clone(),
+                tx: TxId::new(1),
+                tx_type: "deposit",
+                amount: dec!(5.0),
+                held: dec!(1.0),
+                state: "disputed",
+                opened_line: Some(5),
+                age_rows: Some(6),
+                age_seconds: Some(600),
+            },
+            DisputeLine {
+                client: client_2.clone(),
+                tx: TxId::new(2),
+                tx_type: "deposit",
+                amount: dec!(3.0),
+                held: dec!(0),
+                state: "resolved",
+                opened_line: Some(6),
+                age_rows: Some(5),
+                age_seconds: Some(500),
+            },
+        ]);
+        assert_eq!(open_disputes(&transaction_info, std::slice::from_ref(&client_2), None).len(), 1);
+        assert_eq!(open_disputes(&transaction_info, &[], Some("resolved"))[0].client, client_2);
+        assert!(open_disputes(&transaction_info, std::slice::from_ref(&client_1), Some("resolved")).is_empty());
+
+        // Like a run with --state, the disputes reloaded from the event log
+        // keep their opening line, and their age goes on with the next rows.
+        let mut reloaded = replayed(&transaction_info, engine());
+        assert_eq!(open_disputes(&reloaded, &[], None), disputes);
+        let next = "\
+type, client, tx, amount, timestamp
+deposit, 3, 5, 1.0, 1100";
+        let result = proccess_input(&mut ReaderBuilder::new().trim(Trim::All).from_reader(next.as_bytes()), &mut reloaded);
+        assert!(result.is_ok());
+        let disputes = open_disputes(&reloaded, &[], None);
+        assert_eq!(disputes[0].opened_line, Some(5));
+        assert_eq!(disputes[0].age_rows, Some(7));
+        assert_eq!(disputes[0].age_seconds, Some(700));
+        assert_eq!(disputes[0].held, dec!(1.0));
+    }
+
+    #[test]
+    fn check_reconcile() {
+        let before = "\
+client, available, held, total, locked
+1, 3.5, 0, 3.5, false
+2, 2.0, 1.0, 3.0, false
+3, 1.0, 0, 1.0, false";
+        let after = "\
+client, available, held, total, locked
+1, 3.50001, 0, 3.50001, false
+2, 3.0, 0, 3.0, true
+4, 1.0, 0, 1.0, false";
+        let before = read_accounts(&mut ReaderBuilder::new().trim(Trim::All).from_reader(before.as_bytes())).unwrap();
+        let after = read_accounts(&mut ReaderBuilder::new().trim(Trim::All).from_reader(after.as_bytes())).unwrap();
+
+        let changed = |client: u16, field, old: &str, new: &str| Difference {
+            client: ClientID::new(client),
+            status: "changed",
+            field: Some(field),
+            before: Some(old.to_string()),
+            after: Some(new.to_string()),
+        };
+        let only = |client: u16, status| Difference { client: ClientID::new(client), status, field: None, before: None, after: None };
+        assert_eq!(reconcile(&before, &after, dec!(0.0001)), vec![
+            changed(2, "available", "2", "3"),
+            changed(2, "held", "1", "0"),
+            changed(2, "locked", "false", "true"),
+            only(3, "removed"),
+            only(4, "added"),
+        ]);
+        assert_eq!(reconcile(&before, &after, dec!(0)).first(), Some(&changed(1, "available", "3.5", "3.50001")));
+        assert!(reconcile(&before, &before, dec!(0)).is_empty());
+    }
+
+    #[test]
+    fn check_generator() {
+        let config = GeneratorConfig { clients: 20, rows: 5000, dispute_rate: 0.1, invalid_rate: 0.1, seed: 42, ..GeneratorConfig::default() };
+        let mut generator = Generator::new(config.clone());
+        let mut buffer = ByteBuffer::new();
+        {
+            let mut wtr = csv::WriterBuilder::new().from_writer(&mut buffer);
+            assert!(output_rows(&mut wtr, &mut generator).is_ok());
+        }
+        let rows: Vec<_> = Generator::new(config).collect();
+        assert_eq!(rows.len(), 5000);
+        assert!(rows.iter().any(|row| row.tx_type == TxType::Chargeback));
+
+        let data = buffer.to_bytes();
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_slice());
+        let mut transaction_info = engine();
+        let result = proccess_input(&mut rdr, &mut transaction_info);
+        assert!(result.is_ok());
+        let mut expected = generator.get_model().clients();
+        let mut clients: Vec<Client> = transaction_info.get_clients().values().cloned().collect();
+        expected.sort_by_key(|client| client.client.value());
+        clients.sort_by_key(|client| client.client.value());
+        assert_eq!(clients, expected);
+        assert!(clients.iter().any(|client| client.locked));
+    }
+
+    #[test]
+    fn check_row_parsing() {
+        let rows = "\
+deposit, 1, 1, 0.1234567890123456789
+deposit, 2, 2, 1e3
+withdrawal, 2, 3, 2.5
+dispute, 1, 1,
+resolve, 1, 1,
+deposit, 3, 4, 1.0";
+        let run = |header: &str, trim: Trim| {
+            let data = format!("{}\n{}", header, rows);
+            let mut rdr = ReaderBuilder::new()
+                .trim(trim)
+                .flexible(true)
+                .from_reader(data.as_bytes());
+            let mut transaction_info = engine();
+            let result = proccess_input(&mut rdr, &mut transaction_info);
+            assert!(result.is_ok());
+            transaction_info
+        };
+        let fast = run("type, client, tx, amount", Trim::None);
+        let serde = run("type, client, tx, amount, timestamp", Trim::All);
+        assert_eq!(fast.get_clients(), serde.get_clients());
+        assert_eq!(fast.get_client(&ClientID::new(1)).map(|client| client.held), Some(dec!(0)));
+        assert_eq!(
+            fast.get_client(&ClientID::new(1)).map(|client| client.available.to_string()),
+            Some("0.1234567890123456789".to_string())
+        );
+        assert_eq!(fast.get_client(&ClientID::new(2)).map(|client| client.available), Some(dec!(997.5)));
+        assert_replays(&fast);
+
+    }
+
+    /// Reads a file like before `parse_row`: with serde, from a reader that
+    /// trims the fields and is not flexible.
+    fn serde_input(data: &str) -> (Result<(), String>, TransactionsInfo) {
+        let mut transaction_info = engine();
+        let mut run = || -> Result<(), Box<dyn Error>> {
+            let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(data.as_bytes());
+            let headers = rdr.headers()?.clone();
+            let mut record = StringRecord::new();
+            while rdr.read_record(&mut record)? {
+                let line = record.position().map_or(0, |position| position.line());
+                proccess_row(record.deserialize::<TransactionTemplate>(Some(&headers))?, line, &mut transaction_info)?;
+            }
+            Ok(())
+        };
+        let result = run().map_err(|err| err.to_string());
+        (result, transaction_info)
+    }
+
+    #[test]
+    fn check_row_errors() {
+        for row in [
+            "deposit, 1, 2, r",
+            ", 1, 2, 1.0",
+            "deposit, -1, 2, 1.0",
+            "deposit, 70000, 2, 1.0",
+            "deposit, 1, , 1.0",
+            "deposit, 1, 2, 1.0, 9",
+            "deposit, 1, 2",
+            "dispute, 1, 1",
+            "deposit, 1",
+            "deposit, 1, 2,",
+            "deposit, 1, 2, 1e40",
+        ] {
+            let data = format!("type, client, tx, amount\ndeposit, 1, 1, 2.0\n{}\ndeposit, 1, 3, 1.0", row);
+            let (expected, serde) = serde_input(&data);
+            let mut fast = engine();
+            let result = proccess_input(&mut ReaderBuilder::new().flexible(true).from_reader(data.as_bytes()), &mut fast);
+            let mut parallel = engine();
+            let parallel_result = proccess_chunks(data.as_bytes(), &Profile::default(), 8, &mut parallel);
+            assert_eq!(result.map_err(|err| err.to_string()), expected, "{}", row);
+            assert_eq!(parallel_result.map_err(|err| err.to_string()), expected, "{}", row);
+            assert_eq!(fast.get_clients(), serde.get_clients(), "{}", row);
+            assert_eq!(parallel.get_clients(), serde.get_clients(), "{}", row);
+        }
+
+        let data = "type, client, tx, amount\ndeposit, 1, 1, r\ndeposit, 1, 2";
+        let (result, _) = serde_input(data);
+        assert_eq!(
+            result,
+            Err("CSV deserialize error: record 1 (line: 2, byte: 25): invalid value: string \"r\", expected a Decimal type representing a fixed-point number".to_string())
+        );
+        let (result, _) = serde_input(&data.replace(", r", ", 1"));
+        assert_eq!(
+            result,
+            Err("CSV error: record 2 (line: 3, byte: 42): found record with 3 fields, but the previous record has 4 fields".to_string())
+        );
+        let swapped = "client, type, tx, amount\n1, deposit, 1, r";
+        let result = proccess_input(&mut ReaderBuilder::new().flexible(true).from_reader(swapped.as_bytes()), &mut engine());
+        assert_eq!(result.map_err(|err| err.to_string()), serde_input(swapped).0);
+    }
+
+    #[test]
+    fn check_parallel_input() {
+        let mut buffer = Vec::new();
+        let config = GeneratorConfig { rows: 2000, dispute_rate: 0.2, seed: 7, ..GeneratorConfig::default() };
+        output_rows(&mut csv::Writer::from_writer(&mut buffer), &mut Generator::new(config)).unwrap();
+        let data = String::from_utf8(buffer).unwrap();
+        let run = |data: &str, parallel: bool| {
+            let mut transaction_info = engine();
+            let result = match parallel {
+                true => proccess_chunks(data.as_bytes(), &Profile::default(), 256, &mut transaction_info),
+                false => proccess_input(&mut ReaderBuilder::new().flexible(true).from_reader(data.as_bytes()), &mut transaction_info),
+            };
+            (result.map_err(|err| err.to_string()), transaction_info)
+        };
+
+        let (result, parallel) = run(&data, true);
+        assert_eq!(result, Ok(()));
+        assert_eq!(parallel.get_clients(), run(&data, false).1.get_clients());
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        assert_eq!(parallel.get_clients(), &pool.install(|| run(&data, true).1.get_clients().clone()));
+        assert_eq!(parallel.get_clients(), run(&data.replace('\n', "\r\n"), true).1.get_clients());
+        assert_replays(&parallel);
+
+        let mut lines: Vec<&str> = data.lines().collect();
+        lines[1499] = "deposit, 1, 1, r";
+        let data = lines.join("\n");
+        let (result, parallel) = run(&data, true);
+        let (serial_result, serial) = run(&data, false);
+        assert_eq!(
+            result,
+            Err("CSV deserialize error: record 1499 (line: 1500, byte: 33896): invalid value: string \"r\", expected a Decimal type representing a fixed-point number".to_string())
+        );
+        assert_eq!(result, serial_result);
+        assert_eq!(Summary::new(&parallel).rows_read, 1498);
+        assert_eq!(parallel.get_clients(), serial.get_clients());
+        for ends in ["\r\n", "\n\n", "\r", "\r\n\r\n"] {
+            let data = data.replace('\n', ends);
+            let (result, parallel) = run(&data, true);
+            let (serial_result, serial) = run(&data, false);
+            assert_eq!(result, serial_result, "{:?}", ends);
+            assert_eq!(parallel.get_clients(), serial.get_clients());
+        }
+
+        for data in ["type, client, tx, amount\rdeposit, 1, 1, 2.5", "type, client, tx, amount", ""] {
+            let (result, parallel) = run(data, true);
+            let (serial_result, serial) = run(data, false);
+            assert_eq!(result, serial_result);
+            assert_eq!(parallel.get_clients(), serial.get_clients());
+        }
+
+        let quoted = data.replace("deposit,", "\"\ndeposit\",");
+        let (result, parallel) = run(&quoted, true);
+        let (serial_result, serial) = run(&quoted, false);
+        assert_eq!(result, serial_result);
+        assert_eq!(parallel.get_clients(), serial.get_clients());
+        assert_eq!(Summary::new(&parallel).rows_read, Summary::new(&serial).rows_read);
+
+        let (result, parallel) = run("client, type, tx, amount\n1, deposit, 1, 2.5", true);
+        assert_eq!(result, Ok(()));
+        assert_eq!(parallel.get_client(&ClientID::new(1)).map(|client| client.available), Some(dec!(2.5)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_file_input() {
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::os::fd::OwnedFd;
+
+        let mut buffer = Vec::new();
+        let config = GeneratorConfig { rows: 2000, dispute_rate: 0.2, seed: 3, ..GeneratorConfig::default() };
+        output_rows(&mut csv::Writer::from_writer(&mut buffer), &mut Generator::new(config)).unwrap();
+        let mut expected = engine();
+        proccess_input(&mut ReaderBuilder::new().from_reader(buffer.as_slice()), &mut expected).unwrap();
+
+        let path = std::env::temp_dir().join(format!("check_file_input_{}.csv", std::process::id()));
+        fs::write(&path, &buffer).unwrap();
+        let mut mapped = engine();
+        let result = proccess_file(File::open(&path).unwrap(), &Profile::default(), &mut mapped);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(mapped.get_clients(), expected.get_clients());
+
+        // A pipe, like `/dev/stdin`, cannot be mapped and is read row by row.
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let writing = std::thread::spawn(move || writer.write_all(&buffer));
+        let mut piped = engine();
+        let result = proccess_file(File::from(OwnedFd::from(reader)), &Profile::default(), &mut piped);
+        writing.join().unwrap().unwrap();
+        assert!(result.is_ok());
+        assert_eq!(piped.get_clients(), expected.get_clients());
+    }
+
+    #[test]
+    fn check_partner_profiles() {
+        let profiles = "\
+partner, setting, value
+acme, delimiter, ;
+acme, type, tx_type
+acme, client, client_id
+acme, tx, TransactionID
+globex, headers, false
+globex, client, 1
+globex, type, 2
+globex, tx, 3
+globex, amount, 4";
+        let profile = |partner: &str| {
+            let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(profiles.as_bytes());
+            Profile::load(&mut rdr, partner).map_err(|err| err.to_string())
+        };
+        let run = |partner: &str, data: &str| {
+            let profile = profile(partner).unwrap();
+            let mut transaction_info = engine();
+            transaction_info.keep_history();
+            transaction_info.keep_rejects();
+            let result = proccess_partner_input(&mut profile.reader_builder().from_reader(data.as_bytes()), &profile, &mut transaction_info);
+            (result.map_err(|err| err.to_string()), transaction_info)
+        };
+
+        let data = "\
+client_id; note; TransactionID; amount; tx_type
+1; first; 1; 2.0; deposit
+1; too much; 2; 5.0; withdrawal
+2; ; 3; 1.5; deposit";
+        let (result, acme) = run("acme", data);
+        assert_eq!(result, Ok(()));
+        assert_eq!(acme.get_client(&ClientID::new(1)).map(|client| client.available), Some(dec!(2.0)));
+        assert_eq!(acme.get_client(&ClientID::new(2)).map(|client| client.available), Some(dec!(1.5)));
+        let mut rejects = Vec::new();
+        output_rejects(&mut csv::Writer::from_writer(&mut rejects), acme.get_rejects()).unwrap();
+        assert_eq!(String::from_utf8(rejects).unwrap(), "\
+line,type,client,tx,amount,to_client,timestamp,reason,note
+3,withdrawal,1,2,5.0,,,insufficient funds,too much
+");
+        let lines = client_statement(&acme, &[ClientID::new(1)]);
+        let mut statement = Vec::new();
+        output_statement_csv(&mut csv::Writer::from_writer(&mut statement), &lines).unwrap();
+        assert_eq!(String::from_utf8(statement).unwrap(), "\
+client,line,type,tx,amount,to_client,reason_code,operator,outcome,reason,available,held,total,note
+1,2,deposit,1,2.0,,,,applied,,2.0000,0.0000,2.0000,first
+1,3,withdrawal,2,5.0,,,,rejected,insufficient funds,2.0000,0.0000,2.0000,too much
+");
+        let json = serde_json::to_value(&lines[0]).unwrap();
+        assert_eq!(json["extra"], serde_json::json!({ "note": "first" }));
+        assert_replays(&acme);
+
+        let (result, globex) = run("globex", "1,deposit,1,2.0,ref-1\n1,withdrawal,2,3.0,ref-2\n2,deposit,3,1.0");
+        assert_eq!(result, Ok(()));
+        assert_eq!(globex.get_client(&ClientID::new(1)).map(|client| client.available), Some(dec!(2.0)));
+        assert_eq!(globex.get_client(&ClientID::new(2)).map(|client| client.available), Some(dec!(1.0)));
+        let rejects = globex.get_rejects();
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].line, 2);
+        assert_eq!(rejects[0].row.get_extra(), [("column_5".to_string(), "ref-2".to_string())]);
+
+        assert_eq!(profile("initech"), Err("unknown partner initech".to_string()));
+        let (result, _) = run("acme", "client_id; tx_type; tx; amount\n1; deposit; 1; 1.0");
+        assert_eq!(result, Err("missing column TransactionID for tx".to_string()));
+    }
+
+    #[test]
+    fn check_type_aliases() {
+        let aliases = "\
+alias, type
+dep, deposit
+wd, withdrawal
+cb, chargeback";
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(aliases.as_bytes());
+        let mut profile = Profile::default();
+        profile.aliases = TypeAliases::load(&mut rdr).unwrap();
+        let rows = "\
+Deposit, 1, 1, 5.0
+DEPOSIT, 1, 2, 1.0
+dep, 2, 3, 2.0
+refund, 1, 4, 0.5
+refnd, 1, 5, 3.0
+WD, 1, 6, 1.0
+dispute, 2, 3,
+Resolve, 2, 3,
+cb, 2, 3,";
+        let run = |header: &str, data: String, parallel: bool| {
+            let data = format!("{}\n{}", header, data);
+            let mut transaction_info = engine();
+            transaction_info.keep_rejects();
+            let result = match parallel {
+                true => proccess_chunks(data.as_bytes(), &profile, 32, &mut transaction_info),
+                false => proccess_partner_input(&mut profile.reader_builder().from_reader(data.as_bytes()), &profile, &mut transaction_info),
+            };
+            assert!(result.is_ok());
+            transaction_info
+        };
+        let fast = run("type, client, tx, amount", rows.to_string(), false);
+        let parallel = run("type, client, tx, amount", rows.to_string(), true);
+        let serde = run("type, client, tx, amount, note", rows.replace('\n', ", x\n"), false);
+        for transaction_info in [&fast, &parallel, &serde] {
+            assert_eq!(transaction_info.get_clients(), fast.get_clients());
+            let rejects = transaction_info.get_rejects();
+            assert_eq!(rejects.len(), 1);
+            assert_eq!((rejects[0].line, &rejects[0].row.tx_type), (6, &TxType::Unknown("refnd".to_string())));
+            assert_eq!(rejects[0].rejection.to_string(), "unknown type 'refnd'");
+        }
+        assert_eq!(fast.get_client(&ClientID::new(1)).map(|client| client.available), Some(dec!(5.5)));
+        let client_2 = fast.get_client(&ClientID::new(2)).unwrap();
+        assert_eq!((client_2.available, client_2.total, client_2.locked), (dec!(0), dec!(0), true));
+        let summary = Summary::new(&fast);
+        assert_eq!(summary.rows.get("refund"), Some(&RowOutcomes { applied: 1, rejected: 0 }));
+        assert_eq!(summary.rows.get("unknown"), Some(&RowOutcomes { applied: 0, rejected: 1 }));
+        assert_replays(&fast);
+
+        let mut rejects = Vec::new();
+        output_rejects(&mut csv::Writer::from_writer(&mut rejects), fast.get_rejects()).unwrap();
+        assert_eq!(String::from_utf8(rejects).unwrap(), "\
+line,type,client,tx,amount,to_client,timestamp,reason
+6,refnd,1,5,3.0,,,unknown type 'refnd'
+");
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader("alias, type\ncb, chargebak".as_bytes());
+        assert_eq!(TypeAliases::load(&mut rdr).map_err(|err| err.to_string()), Err("unknown type chargebak for alias cb".to_string()));
+    }
+
+    #[test]
+    fn check_ouput() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 3.9876543";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_info = engine();
         let input_result = proccess_input(&mut rdr, &mut transaction_info);
         assert!(input_result.is_ok());
 
@@ -0,0 +1,234 @@
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use std::error::Error;
+use std::io::Read;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::ingest::{RowError, TypeAliases};
+use crate::transactions::TransactionTemplate;
+
+/// Fields of a row that can be read from a column, by the names of the
+/// columns in the default layout.
+pub const FIELDS: [&str; 6] = ["type", "client", "tx", "amount", "to_client", "timestamp"];
+
+#[derive(Debug, Deserialize)]
+struct ProfileRow {
+    partner: String,
+    setting: String,
+    value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Column {
+    Name(String),
+    Position(usize),
+}
+
+/// How the files of one partner are written, loaded from a CSV file with
+/// `partner, setting, value` rows:
+///
+/// ```text
+/// partner, setting, value
+/// acme, delimiter, ;
+/// acme, type, tx_type
+/// acme, client, client_id
+/// acme, tx, TransactionID
+/// globex, headers, false
+/// globex, client, 1
+/// globex, type, 2
+/// globex, tx, 3
+/// globex, amount, 4
+/// ```
+///
+/// `delimiter` is a single character or `tab`, `headers` tells whether the
+/// first line names the columns. The other settings give the column of one
+/// of `FIELDS`: its name, or its position from 1 in files without headers.
+/// Fields that are not given are read from the column of the same name, or
+/// in files without headers from the positions of `type, client, tx, amount`
+/// when no position is given at all. Columns that are none of the fields
+/// are kept with the row, see `Layout::extra`. Types are read with
+/// `aliases`, which the profiles file does not set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub delimiter: u8,
+    pub headers: bool,
+    pub aliases: TypeAliases,
+    columns: Vec<(&'static str, Column)>,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile { delimiter: b',', headers: true, aliases: TypeAliases::default(), columns: Vec::new() }
+    }
+}
+
+fn parse_delimiter(value: &str) -> Result<u8, Box<dyn Error>> {
+    match value {
+        "tab" => Ok(b'\t'),
+        value if value.len() == 1 => Ok(value.as_bytes()[0]),
+        value => Err(format!("invalid delimiter '{}'", value).into()),
+    }
+}
+
+impl Profile {
+    pub fn from_path(path: &Path, partner: &str) -> Result<Profile, Box<dyn Error>> {
+        Profile::load(&mut ReaderBuilder::new().trim(Trim::All).from_path(path)?, partner)
+    }
+
+    /// Reads the profile of `partner` from a profiles file.
+    pub fn load<R: Read>(rdr: &mut Reader<R>, partner: &str) -> Result<Profile, Box<dyn Error>> {
+        let mut profile = Profile::default();
+        let mut columns = Vec::new();
+        let mut found = false;
+        for result in rdr.deserialize() {
+            let row: ProfileRow = result?;
+            if row.partner != partner {
+                continue;
+            }
+            found = true;
+            match row.setting.as_str() {
+                "delimiter" => profile.delimiter = parse_delimiter(&row.value)?,
+                "headers" => {
+                    profile.headers = row.value.parse().map_err(|_| format!("invalid headers '{}'", row.value))?
+                }
+                setting => match FIELDS.iter().find(|field| **field == setting) {
+                    Some(field) => columns.push((*field, row.value)),
+                    None => return Err(format!("unknown profile setting {}", setting).into()),
+                },
+            }
+        }
+        if !found {
+            return Err(format!("unknown partner {}", partner).into());
+        }
+        for (field, value) in columns {
+            let column = match profile.headers {
+                true => Column::Name(value),
+                false => match value.parse::<usize>() {
+                    Ok(position) if position > 0 => Column::Position(position - 1),
+                    _ => return Err(format!("column of {} must be a position, got '{}'", field, value).into()),
+                },
+            };
+            profile.columns.push((field, column));
+        }
+        Ok(profile)
+    }
+
+    /// A reader for the files of the partner, which `Profile::layout` and
+    /// `proccess_partner_input` expect.
+    pub fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder.delimiter(self.delimiter).has_headers(self.headers).flexible(true);
+        builder
+    }
+
+    fn column(&self, field: &str) -> Option<&Column> {
+        self.columns.iter().find(|(name, _)| *name == field).map(|(_, column)| column)
+    }
+
+    /// The layout of a file, from its first line: the headers, or the first
+    /// row of a file without headers.
+    pub fn layout(&self, first: &StringRecord) -> Result<Layout, Box<dyn Error>> {
+        let mut fields = Vec::new();
+        let names = match self.headers {
+            true => Some(first.iter().map(|name| name.trim().to_string()).collect::<Vec<_>>()),
+            false => None,
+        };
+        for (position, field) in FIELDS.iter().enumerate() {
+            let index = match (self.column(field), &names) {
+                (Some(Column::Position(index)), _) => Some(*index),
+                (Some(Column::Name(name)), Some(names)) => match names.iter().position(|header| header == name) {
+                    Some(index) => Some(index),
+                    None => return Err(format!("missing column {} for {}", name, field).into()),
+                },
+                (None, Some(names)) => names.iter().position(|header| header == field),
+                (None, None) if self.columns.is_empty() && position < 4 => Some(position),
+                _ => None,
+            };
+            fields.push(index);
+        }
+        let headers = FIELDS.iter().zip(&fields).filter(|(_, index)| index.is_some()).map(|(field, _)| *field).collect();
+        Ok(Layout { fields, headers, names, width: first.len(), aliases: self.aliases.clone() })
+    }
+}
+
+/// Where the fields of a row are in the records of one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    fields: Vec<Option<usize>>,
+    headers: StringRecord,
+    names: Option<Vec<String>>,
+    width: usize,
+    aliases: TypeAliases,
+}
+
+impl Layout {
+    /// Whether the records are `type, client, tx, amount` and nothing else,
+    /// so that they can be read by `parse_row`.
+    pub fn is_plain(&self) -> bool {
+        self.width == 4 && self.fields == [Some(0), Some(1), Some(2), Some(3), None, None]
+    }
+
+    /// Reads a row, with `row` as a buffer for its fields in the default
+    /// layout. The type is looked up in the aliases of the profile, the
+    /// columns that are none of the fields are kept with the row. A row may
+    /// leave out the last columns, a row with more fields than the layout is
+    /// an error like in `parse_row`. Other errors are those of serde.
+    pub fn template(&self, record: &StringRecord, row: &mut StringRecord) -> Result<TransactionTemplate, Box<dyn Error>> {
+        if record.len() > self.width {
+            let position = record.position().cloned().unwrap_or_else(csv::Position::new);
+            return Err(Box::new(RowError::Width { position, fields: record.len(), expected: self.width }));
+        }
+        row.clear();
+        for index in self.fields.iter().flatten() {
+            row.push_field(record.get(*index).unwrap_or_default().trim());
+        }
+        row.set_position(record.position().cloned());
+        let tx_type = row.get(0).filter(|_| self.fields[0].is_some()).map(|name| self.aliases.resolve(name.as_bytes()));
+        let mut transaction: TransactionTemplate = row.deserialize(Some(&self.headers))?;
+        if let Some(tx_type) = tx_type {
+            transaction.tx_type = tx_type;
+        }
+        if record.len() > row.len() {
+            transaction.set_extra(self.extra(record));
+        }
+        Ok(transaction)
+    }
+
+    /// The columns of `record` that are none of the fields, by the name of
+    /// their header or as `column_<position>`.
+    pub fn extra(&self, record: &StringRecord) -> Vec<(String, String)> {
+        record
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.fields.contains(&Some(*index)))
+            .map(|(index, value)| {
+                let name = match self.names.as_ref().and_then(|names| names.get(index)) {
+                    Some(name) => name.clone(),
+                    None => format!("column_{}", index + 1),
+                };
+                (name, value.trim().to_string())
+            })
+            .collect()
+    }
+}
+
+/// The names of the extra columns of `rows`, in the order they first
+/// appear, so that rows of different layouts can be written together.
+pub fn extra_columns<'a>(rows: impl IntoIterator<Item = &'a [(String, String)]>) -> Vec<&'a str> {
+    let mut columns = Vec::new();
+    for (name, _) in rows.into_iter().flatten() {
+        if !columns.contains(&name.as_str()) {
+            columns.push(name.as_str());
+        }
+    }
+    columns
+}
+
+/// The values of `extra` in the order of `columns`, empty where it has none.
+pub fn extra_values<'a>(extra: &'a [(String, String)], columns: &[&str]) -> Vec<&'a str> {
+    columns
+        .iter()
+        .map(|column| extra.iter().find(|(name, _)| name == column).map_or("", |(_, value)| value.as_str()))
+        .collect()
+}
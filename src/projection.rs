@@ -0,0 +1,189 @@
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::BufRead;
+
+use crate::events::EngineEvent;
+use crate::ledger::{Account, JournalEntry};
+use crate::transactions::{ClientID, Transaction, TxId, TxType};
+use crate::transactions_info::TransactionsInfo;
+
+fn book_deposit(tx: &TxId, kind: &'static str, client: &ClientID, amount: Decimal, fee: Decimal, house: &Option<ClientID>) -> JournalEntry {
+    let mut entry = JournalEntry::new(tx.clone(), kind)
+        .debit(Account::ExternalSettlement, amount)
+        .credit(Account::ClientAvailable(client.clone()), amount - fee);
+    if let Some(house) = house {
+        entry = entry.credit(Account::ClientAvailable(house.clone()), fee);
+    }
+    entry
+}
+
+fn book_withdrawal(tx: &TxId, client: &ClientID, amount: Decimal, fee: Decimal, house: &Option<ClientID>) -> JournalEntry {
+    let mut entry = JournalEntry::new(tx.clone(), "withdrawal")
+        .debit(Account::ClientAvailable(client.clone()), amount + fee)
+        .credit(Account::ExternalSettlement, amount);
+    if let Some(house) = house {
+        entry = entry.credit(Account::ClientAvailable(house.clone()), fee);
+    }
+    entry
+}
+
+fn move_amount(tx: &TxId, kind: &'static str, amount: Decimal, from: &ClientID, to: &ClientID) -> JournalEntry {
+    JournalEntry::new(tx.clone(), kind)
+        .debit(Account::ClientAvailable(from.clone()), amount)
+        .credit(Account::ClientAvailable(to.clone()), amount)
+}
+
+fn held_amount(tx: &TxId, amount: Decimal, client: &ClientID) -> JournalEntry {
+    JournalEntry::new(tx.clone(), "dispute")
+        .debit(Account::ClientAvailable(client.clone()), amount)
+        .credit(Account::ClientHeld(client.clone()), amount)
+}
+
+fn release_amount(tx: &TxId, amount: Decimal, client: &ClientID) -> JournalEntry {
+    JournalEntry::new(tx.clone(), "resolve")
+        .debit(Account::ClientHeld(client.clone()), amount)
+        .credit(Account::ClientAvailable(client.clone()), amount)
+}
+
+/// Takes back `charged` of a deposit from the client. The part of the
+/// deposit that the client already spent is booked as a chargeback loss.
+fn chargeback_deposit(tx: &TxId, client: &ClientID, amount: Decimal, fee: Decimal, charged: Decimal, house: &Option<ClientID>) -> JournalEntry {
+    let mut entry = JournalEntry::new(tx.clone(), "chargeback")
+        .debit(Account::ClientAvailable(client.clone()), charged)
+        .debit(Account::ChargebackLoss, amount - fee - charged)
+        .credit(Account::ExternalSettlement, amount);
+    if let Some(house) = house {
+        entry = entry.debit(Account::ClientAvailable(house.clone()), fee);
+    }
+    entry
+}
+
+/// Returns a withdrawal and its fee back to the client.
+fn chargeback_withdrawal(tx: &TxId, client: &ClientID, amount: Decimal, fee: Decimal, house: &Option<ClientID>) -> JournalEntry {
+    let mut entry = JournalEntry::new(tx.clone(), "chargeback")
+        .debit(Account::ExternalSettlement, amount)
+        .credit(Account::ClientAvailable(client.clone()), amount + fee);
+    if let Some(house) = house {
+        entry = entry.debit(Account::ClientAvailable(house.clone()), fee);
+    }
+    entry
+}
+
+fn adjust(tx: &TxId, client: &ClientID, amount: Decimal) -> JournalEntry {
+    let account = Account::ClientAvailable(client.clone());
+    if amount.is_sign_negative() {
+        JournalEntry::new(tx.clone(), "adjustment")
+            .debit(account, -amount)
+            .credit(Account::ManualAdjustment, -amount)
+    } else {
+        JournalEntry::new(tx.clone(), "adjustment")
+            .debit(Account::ManualAdjustment, amount)
+            .credit(account, amount)
+    }
+}
+
+/// Applies one event to the projections kept by `transactions_info`: the
+/// client balances, the stored transactions, their dispute states and
+/// dispute records, the evicted transactions, the closed accounts, the
+/// journal, the position in the input, the limit counters and the state of
+/// the fraud rules. This is the only place where they change, so replaying
+/// the events of a run rebuilds the same state.
+pub fn apply(event: &EngineEvent, transactions_info: &mut TransactionsInfo) {
+    match event {
+        EngineEvent::RowRead { line, timestamp } => transactions_info.start_row(*line, *timestamp),
+        EngineEvent::AccountOpened { client } => transactions_info.open_client(client.clone()),
+        EngineEvent::Deposited { client, tx, amount, fee, house } => {
+            transactions_info.post(book_deposit(tx, "deposit", client, *amount, *fee, house));
+            transactions_info.insert_transaction(
+                (tx.clone(), client.clone()),
+                Transaction::Deposit { amount: *amount, fee: *fee },
+            );
+            transactions_info.record_limits(&TxType::Deposit, client, *amount);
+            transactions_info.evaluate_rules(&TxType::Deposit, client, tx, Some(*amount));
+        }
+        EngineEvent::Refunded { client, tx, amount, fee, house } => {
+            transactions_info.post(book_deposit(tx, "refund", client, *amount, *fee, house));
+            transactions_info.insert_transaction(
+                (tx.clone(), client.clone()),
+                Transaction::Deposit { amount: *amount, fee: *fee },
+            );
+        }
+        EngineEvent::Withdrew { client, tx, amount, fee, house } => {
+            transactions_info.post(book_withdrawal(tx, client, *amount, *fee, house));
+            transactions_info.insert_transaction(
+                (tx.clone(), client.clone()),
+                Transaction::Withdrawal { amount: *amount, fee: *fee },
+            );
+            transactions_info.record_limits(&TxType::Withdrawal, client, *amount);
+            transactions_info.evaluate_rules(&TxType::Withdrawal, client, tx, Some(*amount));
+        }
+        EngineEvent::WithdrawalRejected { .. } => (),
+        EngineEvent::Transferred { client, tx, to_client, amount } => {
+            transactions_info.post(move_amount(tx, "transfer", *amount, client, to_client));
+            transactions_info.insert_transaction(
+                (tx.clone(), client.clone()),
+                Transaction::Transfer { to_client: to_client.clone(), amount: *amount },
+            );
+        }
+        EngineEvent::Disputed { client, tx, held } => {
+            let tx_and_client_ids = (tx.clone(), client.clone());
+            match transactions_info.get_transaction(&tx_and_client_ids) {
+                Some(Transaction::Deposit { .. }) => transactions_info.post(held_amount(tx, *held, client)),
+                Some(Transaction::Transfer { to_client, .. }) => transactions_info.post(held_amount(tx, *held, &to_client)),
+                _ => (),
+            }
+            transactions_info.record_dispute(tx_and_client_ids.clone(), *held);
+            transactions_info.insert_dispute(tx_and_client_ids, Transaction::Dispute);
+            transactions_info.evaluate_rules(&TxType::Dispute, client, tx, None);
+        }
+        EngineEvent::Resolved { client, tx, released } => {
+            let tx_and_client_ids = (tx.clone(), client.clone());
+            match transactions_info.get_transaction(&tx_and_client_ids) {
+                Some(Transaction::Deposit { .. }) => transactions_info.post(release_amount(tx, *released, client)),
+                Some(Transaction::Transfer { to_client, .. }) => {
+                    transactions_info.post(release_amount(tx, *released, &to_client))
+                }
+                _ => (),
+            }
+            transactions_info.insert_dispute(tx_and_client_ids, Transaction::Resolve);
+        }
+        EngineEvent::ChargedBack { client, tx, amount: charged, house } => {
+            let tx_and_client_ids = (tx.clone(), client.clone());
+            match transactions_info.get_transaction(&tx_and_client_ids) {
+                Some(Transaction::Deposit { amount, fee }) => {
+                    transactions_info.post(chargeback_deposit(tx, client, amount, fee, *charged, house))
+                }
+                Some(Transaction::Withdrawal { amount, fee }) => {
+                    transactions_info.post(chargeback_withdrawal(tx, client, amount, fee, house))
+                }
+                Some(Transaction::Transfer { to_client, .. }) => {
+                    transactions_info.post(move_amount(tx, "chargeback", *charged, &to_client, client))
+                }
+                _ => (),
+            }
+            transactions_info.remove_dispute_record(&tx_and_client_ids);
+            transactions_info.insert_dispute(tx_and_client_ids, Transaction::Chargeback);
+            transactions_info.evaluate_rules(&TxType::Chargeback, client, tx, None);
+        }
+        EngineEvent::Locked { client, rule } => transactions_info.set_locked(client.clone(), true, rule.is_some()),
+        EngineEvent::Unlocked { client } => transactions_info.set_locked(client.clone(), false, false),
+        EngineEvent::Closed { client } => transactions_info.close_client(client.clone()),
+        EngineEvent::Adjusted { client, tx, amount } => transactions_info.post(adjust(tx, client, *amount)),
+        EngineEvent::Evicted { client, tx } => transactions_info.evict_transaction((tx.clone(), client.clone())),
+    }
+}
+
+/// Rebuilds the projections from an NDJSON event log, one event per line.
+/// The limit counters and the fraud rules are only rebuilt if
+/// `transactions_info` has the limits and rules of the run.
+pub fn replay<R: BufRead>(reader: R, transactions_info: &mut TransactionsInfo) -> Result<(), Box<dyn Error>> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: EngineEvent = serde_json::from_str(&line)?;
+        apply(&event, transactions_info);
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use csv::{Reader, Writer};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+
+use serde::Serialize;
+
+use crate::transactions::{Client, ClientID};
+
+/// A client that is only in one snapshot, or a field that differs between
+/// the two snapshots by more than the tolerance.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub client: ClientID,
+    pub status: &'static str,
+    pub field: Option<&'static str>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Reads accounts in the format written by `output_client_data`.
+pub fn read_accounts<R: Read>(rdr: &mut Reader<R>) -> Result<HashMap<ClientID, Client>, Box<dyn Error>> {
+    let mut clients = HashMap::new();
+    for result in rdr.deserialize() {
+        let client: Client = result?;
+        clients.insert(client.client.clone(), client);
+    }
+    Ok(clients)
+}
+
+fn changed(client: &ClientID, field: &'static str, before: impl ToString, after: impl ToString) -> Difference {
+    Difference {
+        client: client.clone(),
+        status: "changed",
+        field: Some(field),
+        before: Some(before.to_string()),
+        after: Some(after.to_string()),
+    }
+}
+
+/// Differences between the `before` and `after` snapshots, by client id.
+/// Amounts that differ by at most `tolerance` are equal.
+pub fn reconcile(before: &HashMap<ClientID, Client>, after: &HashMap<ClientID, Client>, tolerance: Decimal) -> Vec<Difference> {
+    let mut client_ids: Vec<&ClientID> = before.keys().chain(after.keys().filter(|id| !before.contains_key(id))).collect();
+    client_ids.sort_by_key(|client_id| client_id.value());
+    let mut differences = Vec::new();
+    for client_id in client_ids {
+        let (old, new) = match (before.get(client_id), after.get(client_id)) {
+            (Some(old), Some(new)) => (old, new),
+            (old, _) => {
+                let status = if old.is_some() { "removed" } else { "added" };
+                differences.push(Difference { client: client_id.clone(), status, field: None, before: None, after: None });
+                continue;
+            }
+        };
+        let amounts = [
+            ("available", old.available, new.available),
+            ("held", old.held, new.held),
+            ("total", old.total, new.total),
+        ];
+        for (field, old_amount, new_amount) in amounts {
+            if (old_amount - new_amount).abs() > tolerance {
+                differences.push(changed(client_id, field, old_amount, new_amount));
+            }
+        }
+        if old.locked != new.locked {
+            differences.push(changed(client_id, "locked", old.locked, new.locked));
+        }
+    }
+    differences
+}
+
+pub fn output_differences<W: Write>(
+    wtr: &mut Writer<W>,
+    differences: &[Difference],
+) -> Result<(), Box<dyn Error>> {
+    for difference in differences {
+        wtr.serialize(difference)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use csv::Writer;
+use std::error::Error;
+use std::io::Write;
+
+use crate::profile::{extra_columns, extra_values};
+use crate::transactions::{Rejection, TransactionTemplate, TxType};
+
+/// Columns of the rejects file. The extra columns of the rows follow them.
+pub const REJECT_COLUMNS: [&str; 8] = ["line", "type", "client", "tx", "amount", "to_client", "timestamp", "reason"];
+
+/// A transaction row that the engine rejected.
+#[derive(Debug, Clone)]
+pub struct RejectEntry {
+    pub line: u64,
+    pub row: TransactionTemplate,
+    pub rejection: Rejection,
+}
+
+/// Writes the rejected rows as they were read, with the reason they were
+/// rejected and the columns of the input that are none of the fields. An
+/// unknown type is written as it was in the input.
+pub fn output_rejects<W: Write>(wtr: &mut Writer<W>, rejects: &[RejectEntry]) -> Result<(), Box<dyn Error>> {
+    let columns = extra_columns(rejects.iter().map(|entry| entry.row.get_extra()));
+    wtr.write_record(REJECT_COLUMNS.iter().chain(&columns))?;
+    for entry in rejects {
+        let row = &entry.row;
+        let tx_type = match &row.tx_type {
+            TxType::Unknown(name) => name.as_str(),
+            tx_type => tx_type.name(),
+        };
+        wtr.serialize((
+            entry.line,
+            tx_type,
+            row.get_client(),
+            row.get_tx(),
+            row.get_amount(),
+            row.get_to_client(),
+            row.get_timestamp(),
+            entry.rejection.to_string(),
+            extra_values(row.get_extra(), &columns),
+        ))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
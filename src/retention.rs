@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::transactions::{ClientID, TxId};
+
+/// How long a stored transaction can still be disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// Number of transactions stored after it.
+    Rows(u64),
+    /// Seconds between its `timestamp` and the latest one seen.
+    Seconds(u64),
+}
+
+/// Order in which the stored transactions leave the dispute window.
+#[derive(Debug, Clone)]
+pub struct Retention {
+    window: DisputeWindow,
+    queue: VecDeque<(u64, (TxId, ClientID))>,
+    stored: u64,
+    now: u64,
+}
+
+impl Retention {
+    pub fn new(window: DisputeWindow) -> Retention {
+        Retention {
+            window,
+            queue: VecDeque::new(),
+            stored: 0,
+            now: 0,
+        }
+    }
+
+    fn position(&self) -> u64 {
+        match self.window {
+            DisputeWindow::Rows(_) => self.stored,
+            DisputeWindow::Seconds(_) => self.now,
+        }
+    }
+
+    /// Moves the clock to `timestamp`. Rows without a timestamp keep the
+    /// time of the last row that had one.
+    pub fn advance(&mut self, timestamp: Option<u64>) {
+        if let Some(timestamp) = timestamp {
+            self.now = self.now.max(timestamp);
+        }
+    }
+
+    pub fn stored(&mut self, key: (TxId, ClientID)) {
+        self.stored += 1;
+        self.queue.push_back((self.position(), key));
+    }
+
+    /// Puts a transaction that has to stay, like one under dispute, back at
+    /// the end of the window.
+    pub fn requeue(&mut self, key: (TxId, ClientID)) {
+        self.queue.push_back((self.position(), key));
+    }
+
+    /// Takes out the transactions that left the window.
+    pub fn expired(&mut self) -> Vec<(TxId, ClientID)> {
+        let (position, window) = match self.window {
+            DisputeWindow::Rows(rows) => (self.stored, rows),
+            DisputeWindow::Seconds(seconds) => (self.now, seconds),
+        };
+        let mut expired = Vec::new();
+        while let Some((stored_at, _)) = self.queue.front() {
+            if position - stored_at <= window {
+                break;
+            }
+            if let Some((_, key)) = self.queue.pop_front() {
+                expired.push(key);
+            }
+        }
+        expired
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+}
+
+/// Entries and an estimate of the bytes used by the engine state that grows
+/// with the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub clients: usize,
+    pub transactions: usize,
+    pub disputes: usize,
+    pub evicted: usize,
+    pub bytes: usize,
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "memory: {} clients, {} transactions, {} disputes, {} evicted, about {} bytes",
+            self.clients, self.transactions, self.disputes, self.evicted, self.bytes
+        )
+    }
+}
@@ -0,0 +1,176 @@
+use csv::{ReaderBuilder, Trim, Writer};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::transactions::{ClientID, TxId, TxType};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// Disputes per applied deposit or withdrawal of the client.
+    DisputeRatio,
+    /// Chargebacks of the client.
+    ChargebackCount,
+    /// A withdrawal at most `window` rows after a deposit of at least
+    /// `threshold`. Rows are counted across inputs, see
+    /// `TransactionsInfo::get_current_row`.
+    WithdrawalAfterLargeDeposit,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Alert,
+    Lock,
+}
+
+/// One row of the rules file:
+///
+/// ```text
+/// rule, threshold, window, action
+/// dispute_ratio, 0.5, , alert
+/// chargeback_count, 2, , lock
+/// withdrawal_after_large_deposit, 1000, 3, alert
+/// ```
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rule {
+    pub rule: RuleKind,
+    pub threshold: Decimal,
+    pub window: Option<u64>,
+    pub action: RuleAction,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub line: u64,
+    pub client: ClientID,
+    pub tx: TxId,
+    pub rule: RuleKind,
+    pub value: Decimal,
+    pub threshold: Decimal,
+    pub action: RuleAction,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClientActivity {
+    deposits: u64,
+    withdrawals: u64,
+    disputes: u64,
+    chargebacks: u64,
+    large_deposit: Option<(u64, Decimal)>,
+}
+
+/// Evaluates the rules on every applied row and collects the alerts. It
+/// never changes balances; a `lock` action is returned to the engine.
+/// `large_deposit` is the row of the deposit, counted across inputs, and
+/// not its line.
+#[derive(Debug, Clone)]
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+    activity: HashMap<ClientID, ClientActivity>,
+    alerts: Vec<Alert>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: Vec<Rule>) -> RuleEngine {
+        RuleEngine {
+            rules,
+            activity: HashMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<RuleEngine, Box<dyn Error>> {
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+        let mut rules = Vec::new();
+        for result in rdr.deserialize() {
+            rules.push(result?);
+        }
+        Ok(RuleEngine::new(rules))
+    }
+
+    pub fn get_alerts(&self) -> &[Alert] {
+        &self.alerts
+    }
+
+    /// Updates the client activity with an applied row, at `line` of its
+    /// input and at `row` of all inputs, and returns the first matching rule
+    /// that asks to lock the client.
+    pub fn evaluate(&mut self, line: u64, row: u64, tx_type: &TxType, client: &ClientID, tx: &TxId, amount: Option<Decimal>) -> Option<RuleKind> {
+        let activity = self.activity.entry(client.clone()).or_default();
+        match tx_type {
+            TxType::Deposit => activity.deposits += 1,
+            TxType::Withdrawal => activity.withdrawals += 1,
+            TxType::Dispute => activity.disputes += 1,
+            TxType::Chargeback => activity.chargebacks += 1,
+            _ => (),
+        }
+        let activity = activity.clone();
+
+        let mut lock = None;
+        for rule in self.rules.iter() {
+            let value = match rule.rule {
+                RuleKind::DisputeRatio if *tx_type == TxType::Dispute => {
+                    let transactions = activity.deposits + activity.withdrawals;
+                    Some(Decimal::from(activity.disputes) / Decimal::from(transactions.max(1)))
+                        .filter(|ratio| *ratio > rule.threshold)
+                }
+                RuleKind::ChargebackCount if *tx_type == TxType::Chargeback => {
+                    Some(Decimal::from(activity.chargebacks)).filter(|count| *count >= rule.threshold)
+                }
+                RuleKind::WithdrawalAfterLargeDeposit if *tx_type == TxType::Withdrawal => activity
+                    .large_deposit
+                    .filter(|(_, deposit)| *deposit >= rule.threshold)
+                    .filter(|(deposit_row, _)| row.saturating_sub(*deposit_row) <= rule.window.unwrap_or(1))
+                    .map(|(_, deposit)| deposit),
+                _ => None,
+            };
+            if let Some(value) = value {
+                if rule.action == RuleAction::Lock {
+                    lock.get_or_insert(rule.rule.clone());
+                }
+                self.alerts.push(Alert {
+                    line,
+                    client: client.clone(),
+                    tx: tx.clone(),
+                    rule: rule.rule.clone(),
+                    value,
+                    threshold: rule.threshold,
+                    action: rule.action.clone(),
+                });
+            }
+        }
+
+        if *tx_type == TxType::Deposit {
+            let amount = amount.unwrap_or(dec!(0));
+            let is_large = self
+                .rules
+                .iter()
+                .any(|rule| rule.rule == RuleKind::WithdrawalAfterLargeDeposit && amount >= rule.threshold);
+            if let (true, Some(activity)) = (is_large, self.activity.get_mut(client)) {
+                activity.large_deposit = Some((row, amount));
+            }
+        }
+        lock
+    }
+
+    pub fn output_alerts_csv<W: Write>(&self, wtr: &mut Writer<W>) -> Result<(), Box<dyn Error>> {
+        for alert in self.alerts.iter() {
+            wtr.serialize(alert)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    pub fn output_alerts_json<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(&mut *writer, &self.alerts)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use csv::Writer;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io::Write;
+
+use serde::{Serialize, Serializer};
+
+use crate::profile::{extra_columns, extra_values};
+use crate::transactions::{ClientID, Outcome, TxId};
+use crate::transactions_info::{HistoryRow, TransactionsInfo};
+
+/// Columns of the CSV statement. The extra columns of the rows follow them.
+pub const STATEMENT_COLUMNS: [&str; 13] = [
+    "client", "line", "type", "tx", "amount", "to_client", "reason_code", "operator", "outcome", "reason", "available",
+    "held", "total",
+];
+
+/// One row of a client statement: the input row, what the engine did with
+/// it and the client balances right after it. Admin rows also show their
+/// reason code and operator, transaction rows the columns of the input that
+/// are none of the fields.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    pub client: ClientID,
+    pub line: u64,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+    pub tx: TxId,
+    pub amount: Option<Decimal>,
+    pub to_client: Option<ClientID>,
+    pub reason_code: Option<String>,
+    pub operator: Option<String>,
+    pub outcome: &'static str,
+    pub reason: Option<String>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    #[serde(skip_serializing_if = "Vec::is_empty", serialize_with = "serialize_extra")]
+    pub extra: Vec<(String, String)>,
+}
+
+fn serialize_extra<S: Serializer>(extra: &[(String, String)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(extra.iter().map(|(name, value)| (name, value)))
+}
+
+/// Builds a chronological statement for every client in `client_ids` from
+/// the history kept by `transactions_info`.
+pub fn client_statement(transactions_info: &TransactionsInfo, client_ids: &[ClientID]) -> Vec<StatementLine> {
+    let mut lines = Vec::new();
+    for client_id in client_ids {
+        for entry in transactions_info.get_history() {
+            let balance = match entry.balances.iter().find(|client| &client.client == client_id) {
+                Some(balance) => balance,
+                None => continue,
+            };
+            let (outcome, reason) = match &entry.outcome {
+                Outcome::Applied => ("applied", None),
+                Outcome::Rejected(rejection) => ("rejected", Some(rejection.to_string())),
+            };
+            let mut balance = balance.clone();
+            balance.rescale(4);
+            let (to_client, reason_code, operator, extra) = match &entry.row {
+                HistoryRow::Transaction(row) => (row.get_to_client().cloned(), None, None, row.get_extra().to_vec()),
+                HistoryRow::Admin(row) => {
+                    (None, Some(row.get_reason().to_string()), Some(row.get_operator().to_string()), Vec::new())
+                }
+            };
+            lines.push(StatementLine {
+                client: client_id.clone(),
+                line: entry.line,
+                tx_type: entry.row.type_name(),
+                tx: entry.row.get_tx().clone(),
+                amount: entry.row.get_amount(),
+                to_client,
+                reason_code,
+                operator,
+                outcome,
+                reason,
+                available: balance.available,
+                held: balance.held,
+                total: balance.total,
+                extra,
+            });
+        }
+    }
+    lines
+}
+
+pub fn output_statement_csv<W: Write>(
+    wtr: &mut Writer<W>,
+    lines: &[StatementLine],
+) -> Result<(), Box<dyn Error>> {
+    let columns = extra_columns(lines.iter().map(|line| line.extra.as_slice()));
+    wtr.write_record(STATEMENT_COLUMNS.iter().chain(&columns))?;
+    for line in lines {
+        wtr.serialize((
+            &line.client,
+            line.line,
+            line.tx_type,
+            &line.tx,
+            line.amount,
+            &line.to_client,
+            &line.reason_code,
+            &line.operator,
+            line.outcome,
+            &line.reason,
+            line.available,
+            line.held,
+            line.total,
+            extra_values(&line.extra, &columns),
+        ))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+pub fn output_statement_json<W: Write>(
+    writer: &mut W,
+    lines: &[StatementLine],
+) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer_pretty(&mut *writer, lines)?;
+    writeln!(writer)?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::events::EngineEvent;
+use crate::transactions::{Outcome, Transaction};
+use crate::transactions_info::TransactionsInfo;
+
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RowOutcomes {
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+/// Counted while the rows are processed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunTotals {
+    pub rows_read: u64,
+    pub rows: BTreeMap<&'static str, RowOutcomes>,
+    pub deposited: Decimal,
+    pub refunded: Decimal,
+    pub withdrawn: Decimal,
+    pub charged_back: Decimal,
+}
+
+impl RunTotals {
+    pub fn count_outcome(&mut self, type_name: &'static str, outcome: &Outcome) {
+        let outcomes = self.rows.entry(type_name).or_default();
+        match outcome {
+            Outcome::Applied => outcomes.applied += 1,
+            Outcome::Rejected(_) => outcomes.rejected += 1,
+        }
+    }
+
+    pub fn count_event(&mut self, event: &EngineEvent) {
+        match event {
+            EngineEvent::Deposited { amount, .. } => self.deposited += amount,
+            EngineEvent::Refunded { amount, .. } => self.refunded += amount,
+            EngineEvent::Withdrew { amount, .. } => self.withdrawn += amount,
+            EngineEvent::ChargedBack { amount, .. } => self.charged_back += amount,
+            _ => (),
+        }
+    }
+}
+
+/// Totals of a run, for operations. An open dispute is one that was not
+/// charged back yet.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub rows_read: u64,
+    pub rows: BTreeMap<&'static str, RowOutcomes>,
+    pub deposited: Decimal,
+    pub refunded: Decimal,
+    pub withdrawn: Decimal,
+    pub held: Decimal,
+    pub charged_back: Decimal,
+    pub locked_accounts: u64,
+    pub open_disputes: u64,
+}
+
+impl Summary {
+    pub fn new(transactions_info: &TransactionsInfo) -> Summary {
+        let totals = transactions_info.get_totals();
+        let clients = transactions_info.get_clients().values();
+        let open_disputes = transactions_info
+            .get_tx_store()
+            .iter_disputes()
+            .filter(|(_, _, _, dispute)| **dispute != Transaction::Chargeback)
+            .count();
+        Summary {
+            rows_read: totals.rows_read,
+            rows: totals.rows.clone(),
+            deposited: totals.deposited,
+            refunded: totals.refunded,
+            withdrawn: totals.withdrawn,
+            held: clients.clone().map(|client| client.held).sum(),
+            charged_back: totals.charged_back,
+            locked_accounts: clients.filter(|client| client.locked).count() as u64,
+            open_disputes: open_disputes as u64,
+        }
+    }
+
+    pub fn output_json<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rows read: {}", self.rows_read)?;
+        for (type_name, outcomes) in self.rows.iter() {
+            writeln!(f, "{}: {} applied, {} rejected", type_name, outcomes.applied, outcomes.rejected)?;
+        }
+        writeln!(f, "deposited: {}", self.deposited)?;
+        writeln!(f, "refunded: {}", self.refunded)?;
+        writeln!(f, "withdrawn: {}", self.withdrawn)?;
+        writeln!(f, "held: {}", self.held)?;
+        writeln!(f, "charged back: {}", self.charged_back)?;
+        writeln!(f, "locked accounts: {}", self.locked_accounts)?;
+        write!(f, "open disputes: {}", self.open_disputes)
+    }
+}
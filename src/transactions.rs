@@ -4,44 +4,150 @@ use std::cmp;
 use std::error;
 use std::fmt;
 
+use crate::events::EngineEvent;
+use crate::ingest::parse_decimal;
+use crate::limits::LimitKind;
 use crate::transactions_info::TransactionsInfo;
 
-use serde::{Deserialize, Serialize};
+use serde::de::{Error as _, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TransactionTemplate {
     #[serde(rename = "type")]
     pub tx_type: TxType,
     client: ClientID,
     tx: TxId,
+    #[serde(default, deserialize_with = "deserialize_amount")]
     amount: Option<Decimal>,
+    #[serde(default)]
+    to_client: Option<ClientID>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(skip)]
+    extra: Vec<(String, String)>,
+}
+
+/// Reads the amount with `parse_decimal`, like `parse_row` does, instead of
+/// through floating point. The error is the one of `Decimal`.
+fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Decimal>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) if !text.is_empty() => parse_decimal(&text)
+            .map(Some)
+            .map_err(|_| D::Error::invalid_value(Unexpected::Str(&text), &"a Decimal type representing a fixed-point number")),
+        _ => Ok(None),
+    }
+}
+
+impl TransactionTemplate {
+    /// A row without `to_client` and `timestamp`.
+    pub fn new(tx_type: TxType, client: ClientID, tx: TxId, amount: Option<Decimal>) -> TransactionTemplate {
+        TransactionTemplate { tx_type, client, tx, amount, to_client: None, timestamp: None, extra: Vec::new() }
+    }
+
+    pub fn get_client(&self) -> &ClientID {
+        &self.client
+    }
+
+    pub fn get_tx(&self) -> &TxId {
+        &self.tx
+    }
+
+    pub fn get_amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    pub fn get_to_client(&self) -> Option<&ClientID> {
+        self.to_client.as_ref()
+    }
+
+    pub fn get_timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Columns of the input row that are none of the fields, by name.
+    pub fn get_extra(&self) -> &[(String, String)] {
+        &self.extra
+    }
+
+    pub fn set_extra(&mut self, extra: Vec<(String, String)>) {
+        self.extra = extra;
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transaction {
-    Deposit { amount: Decimal },
-    Withdrawal { amount: Decimal },
+    Deposit { amount: Decimal, fee: Decimal },
+    Withdrawal { amount: Decimal, fee: Decimal },
+    Transfer { to_client: ClientID, amount: Decimal },
     Dispute,
     Resolve,
     Chargeback,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// The type of a row. A type that is none of the others is kept as it was
+/// written, and the row is rejected.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TxType {
     Deposit,
     Withdrawal,
+    Transfer,
     Dispute,
     Resolve,
     Chargeback,
+    Refund,
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl TxType {
+    /// Every type but `Unknown`.
+    pub const NAMED: [TxType; 7] = [
+        TxType::Deposit,
+        TxType::Withdrawal,
+        TxType::Transfer,
+        TxType::Dispute,
+        TxType::Resolve,
+        TxType::Chargeback,
+        TxType::Refund,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Transfer => "transfer",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Refund => "refund",
+            TxType::Unknown(_) => "unknown",
+        }
+    }
+
+    /// The type named `name`, in any case.
+    pub fn from_name(name: &[u8]) -> Option<TxType> {
+        TxType::NAMED.into_iter().find(|tx_type| tx_type.name().as_bytes().eq_ignore_ascii_case(name))
+    }
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct TxId(u32);
 
 impl TxId {
     pub fn new(id: u32) -> TxId {
-        return TxId(id)
+        TxId(id)
+    }
+
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
@@ -50,11 +156,15 @@ pub struct ClientID(u16);
 
 impl ClientID {
     pub fn new(id: u16) -> ClientID {
-        return ClientID(id)
+        ClientID(id)
+    }
+
+    pub fn value(&self) -> u16 {
+        self.0
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub struct Client {
     pub client: ClientID,
     pub available: Decimal,
@@ -66,7 +176,7 @@ pub struct Client {
 impl Client {
     pub fn empty(client: ClientID) -> Client {
         Client {
-            client: client,
+            client,
             available: dec!(0),
             held: dec!(0),
             total: dec!(0),
@@ -88,12 +198,59 @@ impl Client {
         self.available.rescale(scale);
         self.held.rescale(scale);
         self.total.rescale(scale);
-        return self
+        self
     }
 }
 
 type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
 
+/// Why a valid row did not change any balance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    InsufficientFunds,
+    AccountLocked,
+    SameClient,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    NotResolved,
+    UnknownClient,
+    NotLocked,
+    NonZeroBalance,
+    AccountClosed,
+    LimitExceeded(LimitKind),
+    DisputeWindowExpired,
+    UnknownType(String),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Rejection::InsufficientFunds => "insufficient funds",
+            Rejection::AccountLocked => "account locked",
+            Rejection::SameClient => "transfer to the same client",
+            Rejection::UnknownTransaction => "unknown transaction",
+            Rejection::AlreadyDisputed => "transaction already disputed",
+            Rejection::NotDisputed => "transaction is not disputed",
+            Rejection::NotResolved => "transaction is not resolved",
+            Rejection::UnknownClient => "unknown client",
+            Rejection::NotLocked => "account is not locked",
+            Rejection::NonZeroBalance => "account has funds",
+            Rejection::AccountClosed => "account closed",
+            Rejection::DisputeWindowExpired => "dispute window expired",
+            Rejection::LimitExceeded(limit) => return write!(f, "limit exceeded: {}", limit),
+            Rejection::UnknownType(name) => return write!(f, "unknown type '{}'", name),
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Applied,
+    Rejected(Rejection),
+}
+
 #[derive(Debug, Clone)]
 struct MissingAmountError;
 
@@ -105,134 +262,245 @@ impl fmt::Display for MissingAmountError {
 
 impl error::Error for MissingAmountError {}
 
-pub fn deposit(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<()> {
-    match transaction.amount {
-        Some(amount) => {
-            let client = transactions_info.get_clients_entry(transaction.client.clone());
-            client.available += amount;
-            client.total += amount;
-            transactions_info.insert_transaction(
-                (transaction.tx.clone(), transaction.client.clone()),
-                Transaction::Deposit { amount: amount },
-            );
-            Ok(())
-        },
-        None => {
-            let dyn_err: Box<dyn error::Error> = Box::new(MissingAmountError);
-            Err(dyn_err)
-        }
-    }   
-}
-
-pub fn withdrawal(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<()> {
-    match transaction.amount {
-        Some(amount) => {
-            let client = transactions_info.get_clients_entry(transaction.client.clone());
-            if client.available >= amount {
-                client.available -= amount;
-                client.total -= amount;
-                transactions_info.insert_transaction(
-                    (transaction.tx.clone(), transaction.client.clone()),
-                    Transaction::Withdrawal { amount: amount },
-                );
-            };
-            Ok(())
-        },
-        None => {
-            let dyn_err: Box<dyn error::Error> = Box::new(MissingAmountError);
-            Err(dyn_err)
-        }
+/// Largest amount a row may carry. Balances and run totals are sums of
+/// amounts, keeping every amount far below `Decimal::MAX` keeps them from
+/// overflowing.
+pub const MAX_AMOUNT: Decimal = dec!(1_000_000_000_000_000);
+
+#[derive(Debug, Clone)]
+pub(crate) struct AmountOutOfRangeError(Decimal);
+
+impl fmt::Display for AmountOutOfRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "amount {} is out of range, the limit is {}", self.0, MAX_AMOUNT)
     }
 }
 
-fn held_amount(amount: Decimal, client: &mut Client) {
-    client.available -= amount;
-    client.held += amount;
+impl error::Error for AmountOutOfRangeError {}
+
+/// The amount of a row, which has to be given and at most `MAX_AMOUNT` in
+/// absolute value.
+pub(crate) fn checked_amount(amount: Option<Decimal>) -> Result<Decimal> {
+    match amount {
+        Some(amount) if amount.abs() > MAX_AMOUNT => Err(Box::new(AmountOutOfRangeError(amount))),
+        Some(amount) => Ok(amount),
+        None => Err(Box::new(MissingAmountError)),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MissingRecipientError;
+
+impl fmt::Display for MissingRecipientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing to_client value")
+    }
 }
 
-pub fn dispute(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<()> {
-    let tx_and_client_ids = (transaction.tx, transaction.client.clone());
-    let tx_type = Transaction::Dispute;
-    if !transactions_info.transactions_contains_key(&tx_and_client_ids)
-        || transactions_info.disputes_contains_key(&tx_and_client_ids)
-    {
-        return Ok(());
+impl error::Error for MissingRecipientError {}
+
+
+/// Opens an account for `client` if it has none yet and returns it.
+pub(crate) fn open_account(client: &ClientID, transactions_info: &mut TransactionsInfo) -> Result<Client> {
+    if transactions_info.get_client(client).is_none() {
+        transactions_info.emit(EngineEvent::AccountOpened { client: client.clone() })?;
+    }
+    Ok(transactions_info.get_client(client).cloned().unwrap_or_else(|| Client::empty(client.clone())))
+}
+
+pub fn deposit(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let amount = checked_amount(transaction.amount)?;
+    if transactions_info.is_closed(&transaction.client) {
+        return Ok(Outcome::Rejected(Rejection::AccountClosed));
+    }
+    let day = transactions_info.get_day(transaction.timestamp);
+    if let Some(limit) = transactions_info.check_limits(&TxType::Deposit, &transaction.client, day, amount) {
+        return Ok(Outcome::Rejected(Rejection::LimitExceeded(limit)));
     };
-    let maybe_transaction = transactions_info.get_transaction(&tx_and_client_ids);
-    match maybe_transaction {
-        Some(Transaction::Deposit { amount }) => {
-            let mut client = transactions_info.get_clients_entry(transaction.client.clone());
-            held_amount(cmp::min(client.available, amount), &mut client);
-            transactions_info.insert_dispute(tx_and_client_ids, tx_type);
-        }
-        Some(Transaction::Withdrawal { .. }) => {
-            transactions_info.insert_dispute(tx_and_client_ids, tx_type);
-        }
-        _ => (),
+    let fee = cmp::min(transactions_info.get_fee(&TxType::Deposit, amount), amount.max(Decimal::ZERO));
+    open_account(&transaction.client, transactions_info)?;
+    let house = transactions_info.get_house_client();
+    transactions_info.emit(EngineEvent::Deposited { client: transaction.client, tx: transaction.tx, amount, fee, house })?;
+    Ok(Outcome::Applied)
+}
+
+/// Money paid back to the client, like a refund of a purchase. It is
+/// booked like a deposit, with the fees of refunds, but does not count
+/// against the deposit limits or in the deposited total.
+pub fn refund(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let amount = checked_amount(transaction.amount)?;
+    if transactions_info.is_closed(&transaction.client) {
+        return Ok(Outcome::Rejected(Rejection::AccountClosed));
+    }
+    let fee = cmp::min(transactions_info.get_fee(&TxType::Refund, amount), amount.max(Decimal::ZERO));
+    open_account(&transaction.client, transactions_info)?;
+    let house = transactions_info.get_house_client();
+    transactions_info.emit(EngineEvent::Refunded { client: transaction.client, tx: transaction.tx, amount, fee, house })?;
+    Ok(Outcome::Applied)
+}
+
+fn reject_withdrawal(transaction: &TransactionTemplate, amount: Decimal, rejection: Rejection, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    transactions_info.emit(EngineEvent::WithdrawalRejected {
+        client: transaction.client.clone(),
+        tx: transaction.tx.clone(),
+        amount,
+        reason: rejection.to_string(),
+    })?;
+    Ok(Outcome::Rejected(rejection))
+}
+
+pub fn withdrawal(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let amount = checked_amount(transaction.amount)?;
+    if transactions_info.is_closed(&transaction.client) {
+        return reject_withdrawal(&transaction, amount, Rejection::AccountClosed, transactions_info);
+    }
+    if transactions_info.is_locked(&transaction.client) {
+        return reject_withdrawal(&transaction, amount, Rejection::AccountLocked, transactions_info);
+    }
+    let day = transactions_info.get_day(transaction.timestamp);
+    if let Some(limit) = transactions_info.check_limits(&TxType::Withdrawal, &transaction.client, day, amount) {
+        return reject_withdrawal(&transaction, amount, Rejection::LimitExceeded(limit), transactions_info);
+    };
+    let fee = transactions_info.get_fee(&TxType::Withdrawal, amount);
+    let client = open_account(&transaction.client, transactions_info)?;
+    if client.available < amount + fee {
+        return reject_withdrawal(&transaction, amount, Rejection::InsufficientFunds, transactions_info);
     };
-    Ok(())
+    let house = transactions_info.get_house_client();
+    transactions_info.emit(EngineEvent::Withdrew { client: transaction.client, tx: transaction.tx, amount, fee, house })?;
+    Ok(Outcome::Applied)
 }
 
-fn release_amount(amount: Decimal, client: &mut Client) {
-    client.available += amount;
-    client.held -= amount;
+fn transfer_rejection(from: &ClientID, to: &ClientID, amount: Decimal, transactions_info: &TransactionsInfo) -> Option<Rejection> {
+    if from == to {
+        return Some(Rejection::SameClient);
+    }
+    if transactions_info.is_closed(from) || transactions_info.is_closed(to) {
+        return Some(Rejection::AccountClosed);
+    }
+    let to_locked = transactions_info.get_client(to).is_some_and(|client| client.locked);
+    match transactions_info.get_client(from) {
+        Some(client) if client.locked || to_locked => Some(Rejection::AccountLocked),
+        Some(client) if client.available >= amount => None,
+        _ => Some(Rejection::InsufficientFunds),
+    }
+}
+
+/// Moves `amount` from `client` to `to_client` in one step: either both legs
+/// are applied or none of them is. Like a failed withdrawal, a transfer that
+/// is not allowed is skipped and is not stored, so it cannot be disputed.
+pub fn transfer(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let amount = checked_amount(transaction.amount)?;
+    let to_client = match transaction.to_client {
+        Some(to_client) => to_client,
+        None => return Err(Box::new(MissingRecipientError)),
+    };
+    if let Some(rejection) = transfer_rejection(&transaction.client, &to_client, amount, transactions_info) {
+        return Ok(Outcome::Rejected(rejection));
+    };
+    open_account(&to_client, transactions_info)?;
+    transactions_info.emit(EngineEvent::Transferred { client: transaction.client, tx: transaction.tx, to_client, amount })?;
+    Ok(Outcome::Applied)
+}
+
+fn available(client: &ClientID, transactions_info: &TransactionsInfo) -> Decimal {
+    transactions_info.get_client(client).map_or(dec!(0), |client| client.available)
+}
+
+fn held(client: &ClientID, transactions_info: &TransactionsInfo) -> Decimal {
+    transactions_info.get_client(client).map_or(dec!(0), |client| client.held)
+}
+
+pub fn dispute(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let tx_and_client_ids = (transaction.tx.clone(), transaction.client.clone());
+    if transactions_info.is_evicted(&tx_and_client_ids) {
+        return Ok(Outcome::Rejected(Rejection::DisputeWindowExpired));
+    };
+    if !transactions_info.transactions_contains_key(&tx_and_client_ids) {
+        return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
+    };
+    if transactions_info.disputes_contains_key(&tx_and_client_ids) {
+        return Ok(Outcome::Rejected(Rejection::AlreadyDisputed));
+    };
+    let maybe_transaction = transactions_info.get_transaction(&tx_and_client_ids);
+    let held = match maybe_transaction {
+        Some(Transaction::Deposit { amount, fee }) => {
+            cmp::min(available(&transaction.client, transactions_info), amount - fee)
+        }
+        Some(Transaction::Withdrawal { .. }) => dec!(0),
+        Some(Transaction::Transfer { to_client, amount }) => cmp::min(available(&to_client, transactions_info), amount),
+        _ => return Ok(Outcome::Rejected(Rejection::UnknownTransaction)),
+    };
+    transactions_info.emit(EngineEvent::Disputed { client: transaction.client, tx: transaction.tx, held })?;
+    Ok(Outcome::Applied)
 }
 
-pub fn resolve(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<()> {
-    let tx_and_client_ids = (transaction.tx, transaction.client.clone());
-    let tx_type = Transaction::Resolve;
+pub fn resolve(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let tx_and_client_ids = (transaction.tx.clone(), transaction.client.clone());
+    if transactions_info.is_evicted(&tx_and_client_ids) {
+        return Ok(Outcome::Rejected(Rejection::DisputeWindowExpired));
+    };
     if !transactions_info.transactions_contains_key(&tx_and_client_ids) {
-        return Ok(());
+        return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
     };
     match transactions_info.get_dispute(&tx_and_client_ids) {
         Some(Transaction::Dispute) => (),
-        _ => return Ok(()),
+        _ => return Ok(Outcome::Rejected(Rejection::NotDisputed)),
     };
 
     let maybe_transaction = transactions_info.get_transaction(&tx_and_client_ids);
-    match maybe_transaction {
-        Some(Transaction::Deposit { amount }) => {
-            let mut client = transactions_info.get_clients_entry(transaction.client.clone());
-            release_amount(cmp::min(client.held, amount), &mut client);
-            transactions_info.insert_dispute(tx_and_client_ids, tx_type);
-        }
-        Some(Transaction::Withdrawal { .. }) => {
-            transactions_info.insert_dispute(tx_and_client_ids, tx_type);
+    let released = match maybe_transaction {
+        Some(Transaction::Deposit { amount, fee }) => {
+            cmp::min(held(&transaction.client, transactions_info), amount - fee)
         }
-        _ => (),
+        Some(Transaction::Withdrawal { .. }) => dec!(0),
+        Some(Transaction::Transfer { to_client, amount }) => cmp::min(held(&to_client, transactions_info), amount),
+        _ => return Ok(Outcome::Rejected(Rejection::UnknownTransaction)),
     };
-    Ok(())
+    transactions_info.emit(EngineEvent::Resolved { client: transaction.client, tx: transaction.tx, released })?;
+    Ok(Outcome::Applied)
 }
 
-fn chargeback_and_maybelock(amount: Decimal, client: &mut Client, to_lock: bool) {
-    client.available -= amount;
-    client.total -= amount;
-    client.locked = to_lock;
+/// Locks or unlocks the client after a chargeback. A lock set by a fraud
+/// rule stays.
+fn maybelock(client: &ClientID, to_lock: bool, transactions_info: &mut TransactionsInfo) -> Result<()> {
+    let locked = transactions_info.is_locked(client);
+    match (to_lock, locked) {
+        (true, false) => transactions_info.emit(EngineEvent::Locked { client: client.clone(), rule: None }),
+        (false, true) if !transactions_info.is_locked_by_rule(client) => {
+            transactions_info.emit(EngineEvent::Unlocked { client: client.clone() })
+        }
+        _ => Ok(()),
+    }
 }
 
-pub fn chargeback(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<()> {
-    let tx_and_client_ids = (transaction.tx, transaction.client.clone());
-    let tx_type = Transaction::Chargeback;
+pub fn chargeback(transaction: TransactionTemplate, transactions_info: &mut TransactionsInfo) -> Result<Outcome> {
+    let tx_and_client_ids = (transaction.tx.clone(), transaction.client.clone());
+    if transactions_info.is_evicted(&tx_and_client_ids) {
+        return Ok(Outcome::Rejected(Rejection::DisputeWindowExpired));
+    };
     if !transactions_info.transactions_contains_key(&tx_and_client_ids) {
-        return Ok(());
+        return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
     };
     match transactions_info.get_dispute(&tx_and_client_ids) {
         Some(Transaction::Resolve) => (),
-        _ => return Ok(()),
+        _ => return Ok(Outcome::Rejected(Rejection::NotResolved)),
     };
     let maybe_transaction = transactions_info.get_transaction(&tx_and_client_ids);
-    match maybe_transaction {
-        Some(Transaction::Deposit { amount }) => {
-            let mut client = transactions_info.get_clients_entry(transaction.client.clone());
-            chargeback_and_maybelock(cmp::min(client.available, amount), &mut client, true);
-            transactions_info.insert_dispute(tx_and_client_ids, tx_type);
+    let (charged, to_lock) = match maybe_transaction {
+        Some(Transaction::Deposit { amount, fee }) => {
+            (cmp::min(available(&transaction.client, transactions_info), amount - fee), Some(true))
         }
-        Some(Transaction::Withdrawal { amount }) => {
-            let mut client = transactions_info.get_clients_entry(transaction.client.clone());
-            chargeback_and_maybelock(amount * Decimal::new(-1, 0), &mut client, false);
-            transactions_info.insert_dispute(tx_and_client_ids, tx_type);
+        Some(Transaction::Withdrawal { amount, fee }) => (amount + fee, Some(false)),
+        Some(Transaction::Transfer { to_client, amount }) => {
+            (cmp::min(available(&to_client, transactions_info), amount), None)
         }
-        _ => (),
+        _ => return Ok(Outcome::Rejected(Rejection::UnknownTransaction)),
     };
-    Ok(())
+    let house = transactions_info.get_house_client();
+    transactions_info.emit(EngineEvent::ChargedBack { client: transaction.client.clone(), tx: transaction.tx, amount: charged, house })?;
+    if let Some(to_lock) = to_lock {
+        maybelock(&transaction.client, to_lock, transactions_info)?;
+    }
+    Ok(Outcome::Applied)
 }
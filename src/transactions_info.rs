@@ -1,35 +1,608 @@
-use std::collections::HashMap;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 
-pub use crate::transactions::{Client, ClientID, Transaction, TxId};
+use crate::admin::{AdminTemplate, AuditEntry, Operators};
+use crate::deltas::DeltaStream;
+use crate::disputes::DisputeRecord;
+use crate::events::{EngineEvent, EngineObserver};
+use crate::fees::FeeSchedule;
+use crate::idempotency::{RowIndex, RowStatus};
+use crate::ledger::{apply_posting, Journal, JournalEntry};
+use crate::limits::{LimitCounters, LimitKind, Limits};
+use crate::projection::apply;
+use crate::rejects::RejectEntry;
+use crate::retention::{DisputeWindow, MemoryUsage, Retention};
+use crate::rules::{RuleEngine, RuleKind};
+use crate::summary::RunTotals;
+use crate::tx_store::TxStore;
+pub use crate::transactions::{Client, ClientID, Outcome, Rejection, Transaction, TransactionTemplate, TxId, TxType};
+
+/// A row of the transactions file or of the admin file.
+#[derive(Debug, Clone)]
+pub enum HistoryRow {
+    Transaction(TransactionTemplate),
+    Admin(AdminTemplate),
+}
+
+impl HistoryRow {
+    pub fn get_client(&self) -> &ClientID {
+        match self {
+            HistoryRow::Transaction(row) => row.get_client(),
+            HistoryRow::Admin(row) => row.get_client(),
+        }
+    }
+
+    pub fn get_tx(&self) -> &TxId {
+        match self {
+            HistoryRow::Transaction(row) => row.get_tx(),
+            HistoryRow::Admin(row) => row.get_tx(),
+        }
+    }
+
+    pub fn get_amount(&self) -> Option<Decimal> {
+        match self {
+            HistoryRow::Transaction(row) => row.get_amount(),
+            HistoryRow::Admin(row) => row.get_amount(),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            HistoryRow::Transaction(row) => row.tx_type.name(),
+            HistoryRow::Admin(row) => row.tx_type.name(),
+        }
+    }
+}
+
+/// What happened to one input row, with the balances of every client the
+/// row touched right after it was processed.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub line: u64,
+    pub row: HistoryRow,
+    pub outcome: Outcome,
+    pub balances: Vec<Client>,
+}
 
 pub struct TransactionsInfo {
-    transactions: HashMap<(TxId, ClientID), Transaction>,
+    tx_store: TxStore,
     clients: HashMap<ClientID, Client>,
-    disputes: HashMap<(TxId, ClientID), Transaction>,
+    fee_schedule: Option<FeeSchedule>,
+    journal: Journal,
+    history: Option<Vec<HistoryEntry>>,
+    rejects: Option<Vec<RejectEntry>>,
+    touched_clients: Vec<ClientID>,
+    closed: HashSet<ClientID>,
+    rule_locked: HashSet<ClientID>,
+    audit: Vec<AuditEntry>,
+    current_line: u64,
+    current_row: u64,
+    row_timestamp: Option<u64>,
+    current_timestamp: Option<u64>,
+    dispute_records: HashMap<(TxId, ClientID), DisputeRecord>,
+    limits: Option<Limits>,
+    limit_counters: HashMap<ClientID, LimitCounters>,
+    rules: Option<RuleEngine>,
+    operators: Option<Operators>,
+    rule_lock: Option<RuleKind>,
+    observers: Vec<Box<dyn EngineObserver>>,
+    events: Option<Vec<EngineEvent>>,
+    row_index: Option<RowIndex>,
+    retention: Option<Retention>,
+    deltas: Option<DeltaStream>,
+    totals: RunTotals,
+}
+
+impl Default for TransactionsInfo {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TransactionsInfo {
     pub fn new() -> TransactionsInfo {
         TransactionsInfo {
-            transactions: HashMap::new(),
+            tx_store: TxStore::new(),
             clients: HashMap::new(),
-            disputes: HashMap::new(),
+            fee_schedule: None,
+            journal: Journal::new(false),
+            history: None,
+            rejects: None,
+            touched_clients: Vec::new(),
+            closed: HashSet::new(),
+            rule_locked: HashSet::new(),
+            audit: Vec::new(),
+            current_line: 0,
+            current_row: 0,
+            row_timestamp: None,
+            current_timestamp: None,
+            dispute_records: HashMap::new(),
+            limits: None,
+            limit_counters: HashMap::new(),
+            rules: None,
+            operators: None,
+            rule_lock: None,
+            observers: Vec::new(),
+            events: None,
+            row_index: None,
+            retention: None,
+            deltas: None,
+            totals: RunTotals::default(),
         }
     }
 
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.fee_schedule = Some(fee_schedule);
+    }
+
+    /// The fee of a row, never negative, even for a negative amount.
+    pub fn get_fee(&self, tx_type: &TxType, amount: Decimal) -> Decimal {
+        match &self.fee_schedule {
+            Some(fee_schedule) => fee_schedule.fee(tx_type, amount).max(dec!(0)),
+            None => dec!(0),
+        }
+    }
+
+    /// Client that receives the fees, if a fee schedule is set.
+    pub fn get_house_client(&self) -> Option<ClientID> {
+        self.fee_schedule.as_ref().map(|fee_schedule| fee_schedule.house().clone())
+    }
+
+    /// Keeps every journal entry so it can be exported after the run.
+    pub fn keep_journal(&mut self) {
+        self.journal = Journal::new(true);
+    }
+
+    pub fn get_journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// The only way client balances change: the postings of `entry` are
+    /// applied to the clients and the entry is recorded in the journal.
+    pub fn post(&mut self, entry: JournalEntry) {
+        for posting in entry.postings.iter() {
+            apply_posting(&mut self.clients, posting);
+            if let Some(client_id) = posting.account.client() {
+                self.mark_changed(client_id);
+            }
+        }
+        self.journal.record(entry);
+    }
+
     pub fn get_clients(&self) -> &HashMap<ClientID, Client> {
-        return &self.clients
+        &self.clients
     }
 
     pub fn get_client(&self, client_id: &ClientID) -> Option<&Client> {
-        return self.clients.get(&client_id)
+        self.clients.get(client_id)
+    }
+
+    pub fn is_locked(&self, client_id: &ClientID) -> bool {
+        self.clients.get(client_id).is_some_and(|client| client.locked)
     }
 
-    pub fn get_clients_entry(&mut self, client_id: ClientID) -> &mut Client {
-        return self
-            .clients
+    /// Whether a fraud rule locked the client and it was not unlocked by an
+    /// admin since.
+    pub fn is_locked_by_rule(&self, client_id: &ClientID) -> bool {
+        self.rule_locked.contains(client_id)
+    }
+
+    pub fn open_client(&mut self, client_id: ClientID) {
+        self.mark_changed(&client_id);
+        self.clients
+            .entry(client_id.clone())
+            .or_insert_with(|| Client::empty(client_id));
+    }
+
+    /// Locks or unlocks the client. A lock set by a fraud rule is kept
+    /// apart, see `is_locked_by_rule`.
+    pub fn set_locked(&mut self, client_id: ClientID, locked: bool, by_rule: bool) {
+        self.mark_changed(&client_id);
+        match by_rule {
+            true => self.rule_locked.insert(client_id.clone()),
+            false if !locked => self.rule_locked.remove(&client_id),
+            false => false,
+        };
+        self.clients
             .entry(client_id.clone())
-            .or_insert(Client::empty(client_id));
+            .or_insert_with(|| Client::empty(client_id))
+            .locked = locked;
+    }
+
+    fn mark_changed(&mut self, client_id: &ClientID) {
+        if self.history.is_some() {
+            self.touched_clients.push(client_id.clone());
+        }
+        if let Some(deltas) = self.deltas.as_mut() {
+            deltas.mark(client_id);
+        }
+    }
+
+    /// Streams client rows while the input is processed, see `DeltaStream`.
+    pub fn set_deltas(&mut self, deltas: DeltaStream) {
+        self.deltas = Some(deltas);
+    }
+
+    /// Called after every processed row.
+    pub fn row_done(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.deltas.as_mut() {
+            Some(deltas) => deltas.row_done(&self.clients),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the clients that changed since the last delta rows.
+    pub fn flush_deltas(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.deltas.as_mut() {
+            Some(deltas) => deltas.flush(&self.clients),
+            None => Ok(()),
+        }
+    }
+
+    fn get_client_or_empty(&self, client_id: &ClientID) -> Client {
+        self.clients
+            .get(client_id)
+            .cloned()
+            .unwrap_or_else(|| Client::empty(client_id.clone()))
+    }
+
+    /// Keeps a `HistoryEntry` for every processed row.
+    pub fn keep_history(&mut self) {
+        self.history.get_or_insert_with(Vec::new);
+    }
+
+    pub fn keeps_history(&self) -> bool {
+        self.history.is_some()
+    }
+
+    pub fn get_history(&self) -> &[HistoryEntry] {
+        self.history.as_deref().unwrap_or(&[])
+    }
+
+    pub fn record_history(&mut self, line: u64, row: HistoryRow, outcome: Outcome) {
+        if self.history.is_none() {
+            return;
+        }
+        let mut client_ids = vec![row.get_client().clone()];
+        for client_id in self.touched_clients.drain(..) {
+            if !client_ids.contains(&client_id) {
+                client_ids.push(client_id);
+            }
+        }
+        let balances = client_ids
+            .iter()
+            .map(|client_id| self.get_client_or_empty(client_id))
+            .collect();
+        if let Some(history) = self.history.as_mut() {
+            history.push(HistoryEntry { line, row, outcome, balances });
+        }
+    }
+
+    /// Keeps a `RejectEntry` for every rejected transaction row.
+    pub fn keep_rejects(&mut self) {
+        self.rejects.get_or_insert_with(Vec::new);
+    }
+
+    pub fn keeps_rejects(&self) -> bool {
+        self.rejects.is_some()
+    }
+
+    pub fn get_rejects(&self) -> &[RejectEntry] {
+        self.rejects.as_deref().unwrap_or(&[])
+    }
+
+    pub fn record_reject(&mut self, line: u64, row: TransactionTemplate, rejection: Rejection) {
+        if let Some(rejects) = self.rejects.as_mut() {
+            rejects.push(RejectEntry { line, row, rejection });
+        }
+    }
+
+    /// Balances of every client as they stood right before the row at
+    /// `line` was processed. Needs the history to be kept.
+    pub fn clients_as_of_line(&self, line: u64) -> HashMap<ClientID, Client> {
+        let mut clients = HashMap::new();
+        let rows_before_line = self
+            .get_history()
+            .iter()
+            .take_while(|entry| matches!(entry.row, HistoryRow::Transaction(_)) && entry.line < line);
+        for entry in rows_before_line {
+            for client in entry.balances.iter() {
+                clients.insert(client.client.clone(), client.clone());
+            }
+        }
+        clients
+    }
+
+    /// Line of the first row that used `tx`, which is the row of the
+    /// transaction itself and not of a dispute about it.
+    pub fn line_of_tx(&self, tx: &TxId) -> Option<u64> {
+        self.get_history()
+            .iter()
+            .find(|entry| matches!(entry.row, HistoryRow::Transaction(_)) && entry.row.get_tx() == tx)
+            .map(|entry| entry.line)
+    }
+
+    /// Balances of every client as they stood right before `tx`.
+    pub fn clients_as_of_tx(&self, tx: &TxId) -> Option<HashMap<ClientID, Client>> {
+        self.line_of_tx(tx).map(|line| self.clients_as_of_line(line))
+    }
+
+    /// Moves to the row at `line` of the input, see `EngineEvent::RowRead`.
+    pub fn start_row(&mut self, line: u64, timestamp: Option<u64>) {
+        self.current_line = line;
+        self.current_row += 1;
+        self.row_timestamp = timestamp;
+        self.rule_lock = None;
+        if let Some(timestamp) = timestamp {
+            self.current_timestamp = Some(self.current_timestamp.map_or(timestamp, |current| current.max(timestamp)));
+        }
+    }
+
+    /// Line of the input row being processed.
+    pub fn get_current_line(&self) -> u64 {
+        self.current_line
+    }
+
+    /// Number of rows processed so far, counted across every input and
+    /// every run replayed from the event log.
+    pub fn get_current_row(&self) -> u64 {
+        self.current_row
+    }
+
+    /// Latest `timestamp` of the rows processed so far.
+    pub fn get_current_timestamp(&self) -> Option<u64> {
+        self.current_timestamp
+    }
+
+    /// Remembers that a dispute was opened by the current row, until it is
+    /// charged back.
+    pub fn record_dispute(&mut self, k: (TxId, ClientID), held: Decimal) {
+        let (line, row, timestamp) = (self.current_line, self.current_row, self.row_timestamp);
+        self.dispute_records.insert(k, DisputeRecord { line, row, timestamp, held });
+    }
+
+    pub fn remove_dispute_record(&mut self, k: &(TxId, ClientID)) {
+        self.dispute_records.remove(k);
+    }
+
+    pub fn get_dispute_record(&self, k: &(TxId, ClientID)) -> Option<&DisputeRecord> {
+        self.dispute_records.get(k)
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = Some(limits);
+    }
+
+    /// Day of the current row for the limits, see `Limits::day`.
+    pub fn get_day(&self, timestamp: Option<u64>) -> u64 {
+        match &self.limits {
+            Some(limits) => limits.day(timestamp, self.current_line),
+            None => 0,
+        }
+    }
+
+    pub fn get_limit_counters(&self, client_id: &ClientID) -> Option<&LimitCounters> {
+        self.limit_counters.get(client_id)
+    }
+
+    /// Limit that `amount` would exceed, checked before any balance changes.
+    pub fn check_limits(&self, tx_type: &TxType, client_id: &ClientID, day: u64, amount: Decimal) -> Option<LimitKind> {
+        let limits = self.limits.as_ref()?;
+        let empty = LimitCounters::default();
+        let counters = self.limit_counters.get(client_id).unwrap_or(&empty);
+        match tx_type {
+            TxType::Deposit => limits.check_deposit(counters, day, amount),
+            TxType::Withdrawal => limits.check_withdrawal(counters, day, amount),
+            _ => None,
+        }
+    }
+
+    /// Counts an applied deposit or withdrawal of the current row against
+    /// the limits.
+    pub fn record_limits(&mut self, tx_type: &TxType, client_id: &ClientID, amount: Decimal) {
+        if self.limits.is_none() {
+            return;
+        }
+        let day = self.get_day(self.row_timestamp);
+        let counters = self.limit_counters.entry(client_id.clone()).or_default();
+        match tx_type {
+            TxType::Deposit => counters.record_deposit(day),
+            TxType::Withdrawal => counters.record_withdrawal(day, amount),
+            _ => (),
+        }
+    }
+
+    pub fn set_rules(&mut self, rules: RuleEngine) {
+        self.rules = Some(rules);
+    }
+
+    pub fn get_rules(&self) -> Option<&RuleEngine> {
+        self.rules.as_ref()
+    }
+
+    /// Only these operators may write admin rows, see `authorize`.
+    pub fn set_operators(&mut self, operators: Operators) {
+        self.operators = Some(operators);
+    }
+
+    pub fn get_operators(&self) -> Option<&Operators> {
+        self.operators.as_ref()
+    }
+
+    /// Runs the fraud rules for an applied row of the current row and
+    /// remembers if a matching rule asks to lock the client.
+    pub fn evaluate_rules(&mut self, tx_type: &TxType, client_id: &ClientID, tx: &TxId, amount: Option<Decimal>) {
+        let (line, row) = (self.current_line, self.current_row);
+        if let Some(rules) = self.rules.as_mut() {
+            let lock = rules.evaluate(line, row, tx_type, client_id, tx, amount);
+            self.rule_lock = self.rule_lock.take().or(lock);
+        }
+    }
+
+    /// Locks `client_id` if a fraud rule asked for it during the current row.
+    pub fn lock_by_rules(&mut self, client_id: &ClientID) -> Result<(), Box<dyn Error>> {
+        if let Some(rule) = self.rule_lock.clone().filter(|_| !self.is_locked_by_rule(client_id)) {
+            self.emit(EngineEvent::Locked { client: client_id.clone(), rule: Some(rule) })?;
+        }
+        Ok(())
+    }
+
+    pub fn register_observer(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Applies `event` to the projections and passes it to every
+    /// registered observer.
+    pub fn emit(&mut self, event: EngineEvent) -> Result<(), Box<dyn Error>> {
+        apply(&event, self);
+        self.totals.count_event(&event);
+        for observer in self.observers.iter_mut() {
+            observer.on_event(&event)?;
+        }
+        if let Some(events) = self.events.as_mut() {
+            events.push(event);
+        }
+        Ok(())
+    }
+
+    /// Counts a row read from the input, processed or not.
+    pub fn count_row_read(&mut self) {
+        self.totals.rows_read += 1;
+    }
+
+    pub fn count_outcome(&mut self, type_name: &'static str, outcome: &Outcome) {
+        self.totals.count_outcome(type_name, outcome);
+    }
+
+    pub fn get_totals(&self) -> &RunTotals {
+        &self.totals
+    }
+
+    /// Keeps every emitted event, so the state can be replayed later.
+    pub fn keep_events(&mut self) {
+        self.events.get_or_insert_with(Vec::new);
+    }
+
+    pub fn get_events(&self) -> &[EngineEvent] {
+        self.events.as_deref().unwrap_or(&[])
+    }
+
+    pub fn flush_observers(&mut self) -> Result<(), Box<dyn Error>> {
+        for observer in self.observers.iter_mut() {
+            observer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Skips rows that were already processed, see `RowIndex`.
+    pub fn set_row_index(&mut self, row_index: RowIndex) {
+        self.row_index = Some(row_index);
+    }
+
+    pub fn get_row_index(&self) -> Option<&RowIndex> {
+        self.row_index.as_ref()
+    }
+
+    /// Whether `row` has to be processed: always, unless a row index is set
+    /// and it knows the row already.
+    pub fn admit_row(&mut self, line: u64, row: &TransactionTemplate) -> bool {
+        match self.row_index.as_mut() {
+            Some(row_index) => row_index.check(line, row) == RowStatus::New,
+            None => true,
+        }
+    }
+
+    /// Like `admit_row`, for a row of the admin file.
+    pub fn admit_admin_row(&mut self, line: u64, row: &AdminTemplate) -> bool {
+        match self.row_index.as_mut() {
+            Some(row_index) => row_index.check_admin(line, row) == RowStatus::New,
+            None => true,
+        }
+    }
+
+    /// Stored transactions older than `window` are evicted and can no longer
+    /// be disputed.
+    pub fn set_dispute_window(&mut self, window: DisputeWindow) {
+        self.retention = Some(Retention::new(window));
+    }
+
+    pub fn has_dispute_window(&self) -> bool {
+        self.retention.is_some()
+    }
+
+    /// Called for every processed row: a row that stored a transaction
+    /// starts its window, and every transaction that left its window is
+    /// evicted, unless it is disputed and not charged back yet.
+    pub fn evict_expired(&mut self, stored: Option<(TxId, ClientID)>, timestamp: Option<u64>) -> Result<(), Box<dyn Error>> {
+        let retention = match self.retention.as_mut() {
+            Some(retention) => retention,
+            None => return Ok(()),
+        };
+        retention.advance(timestamp);
+        if let Some(key) = stored {
+            retention.stored(key);
+        }
+        let expired = retention.expired();
+        for key in expired {
+            match self.tx_store.get_dispute(&key.0, &key.1) {
+                Some(Transaction::Dispute) | Some(Transaction::Resolve) => {
+                    if let Some(retention) = self.retention.as_mut() {
+                        retention.requeue(key);
+                    }
+                }
+                _ => self.emit(EngineEvent::Evicted { client: key.1, tx: key.0 })?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn evict_transaction(&mut self, k: (TxId, ClientID)) {
+        self.tx_store.evict(&k.0, &k.1);
+    }
+
+    pub fn is_evicted(&self, k: &(TxId, ClientID)) -> bool {
+        self.tx_store.is_evicted(&k.0, &k.1)
+    }
+
+    /// Estimate of the memory kept for clients and stored transactions, from
+    /// the capacity of their tables.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let key = std::mem::size_of::<(TxId, ClientID)>();
+        let client = std::mem::size_of::<(ClientID, Client)>();
+        let retention = self.retention.as_ref().map_or(0, |retention| retention.capacity());
+        MemoryUsage {
+            clients: self.clients.len(),
+            transactions: self.tx_store.len(),
+            disputes: self.tx_store.disputes(),
+            evicted: self.tx_store.evicted(),
+            bytes: self.clients.capacity() * client
+                + self.tx_store.bytes()
+                + retention * (key + std::mem::size_of::<u64>()),
+        }
+    }
+
+    pub fn is_closed(&self, client_id: &ClientID) -> bool {
+        self.closed.contains(client_id)
+    }
+
+    pub fn close_client(&mut self, client_id: ClientID) {
+        self.closed.insert(client_id);
+    }
+
+    pub fn get_closed(&self) -> &HashSet<ClientID> {
+        &self.closed
+    }
+
+    /// Admin rows are always audited, whether they were applied or not.
+    pub fn record_audit(&mut self, line: u64, row: AdminTemplate, outcome: Outcome) {
+        self.audit.push(AuditEntry { line, row: row.clone(), outcome: outcome.clone() });
+        self.record_history(line, HistoryRow::Admin(row), outcome);
+    }
+
+    pub fn get_audit(&self) -> &[AuditEntry] {
+        &self.audit
     }
 
     pub fn rescale_clients(&mut self, scale: u32) {
@@ -38,31 +611,31 @@ impl TransactionsInfo {
         };
     }
 
+    pub fn get_tx_store(&self) -> &TxStore {
+        &self.tx_store
+    }
+
     pub fn transactions_contains_key(&self, k: &(TxId, ClientID)) -> bool {
-        return self.transactions.contains_key(&k);
+        self.tx_store.contains(&k.0, &k.1)
     }
 
     pub fn get_transaction(&self, k: &(TxId, ClientID)) -> Option<Transaction> {
-        return self.transactions.get(k).map(|t| t.clone());
+        self.tx_store.get(&k.0, &k.1)
     }
 
     pub fn insert_transaction(&mut self, k: (TxId, ClientID), v: Transaction) {
-        self.transactions.insert(k, v);
-    }
-
-    pub fn get_disputes(&self) -> &HashMap<(TxId, ClientID), Transaction> {
-        return &self.disputes;
+        self.tx_store.insert(&k.0, &k.1, &v);
     }
 
     pub fn disputes_contains_key(&self, k: &(TxId, ClientID)) -> bool {
-        return self.disputes.contains_key(&k);
+        self.tx_store.get_dispute(&k.0, &k.1).is_some()
     }
 
     pub fn get_dispute(&self, k: &(TxId, ClientID)) -> Option<&Transaction> {
-        return self.disputes.get(k);
+        self.tx_store.get_dispute(&k.0, &k.1)
     }
 
     pub fn insert_dispute(&mut self, k: (TxId, ClientID), v: Transaction) {
-        self.disputes.insert(k, v);
+        self.tx_store.set_dispute(&k.0, &k.1, &v);
     }
 }
@@ -0,0 +1,571 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+
+use crate::transactions::{ClientID, Transaction, TxId};
+
+const PAGE_BITS: u32 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const DENSE_SLOTS: usize = PAGE_SIZE / 4;
+const EVICTED_WORDS: usize = PAGE_SIZE / 64;
+
+const KIND_MASK: u8 = 0b0000_0011;
+const KIND_DEPOSIT: u8 = 1;
+const KIND_WITHDRAWAL: u8 = 2;
+const KIND_TRANSFER: u8 = 3;
+const DISPUTE_SHIFT: u8 = 2;
+const DISPUTE_MASK: u8 = 0b0000_1100;
+
+/// One stored transaction: its amount, its client and a state byte with the
+/// kind of transaction and the dispute state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Slot {
+    amount: Decimal,
+    client: u16,
+    state: u8,
+}
+
+impl Slot {
+    fn kind(&self) -> u8 {
+        self.state & KIND_MASK
+    }
+
+    fn dispute(&self) -> u8 {
+        (self.state & DISPUTE_MASK) >> DISPUTE_SHIFT
+    }
+}
+
+/// The slots of `PAGE_SIZE` consecutive tx ids. A page keeps only its
+/// used slots until `DENSE_SLOTS` of them are, then all of its slots, so
+/// that sparse tx ids do not cost a whole page each. It goes back to its
+/// used slots when evictions leave fewer than half of that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Page {
+    Sparse(BTreeMap<u16, Slot>),
+    Dense { slots: Box<[Slot]>, used: usize },
+}
+
+impl Page {
+    fn get(&self, index: usize) -> Option<&Slot> {
+        match self {
+            Page::Sparse(slots) => slots.get(&(index as u16)),
+            Page::Dense { slots, .. } => slots.get(index),
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut Slot> {
+        match self {
+            Page::Sparse(slots) => slots.get_mut(&(index as u16)),
+            Page::Dense { slots, .. } => slots.get_mut(index),
+        }
+    }
+
+    fn set(&mut self, index: usize, slot: Slot) {
+        match self {
+            Page::Sparse(slots) if slots.len() < DENSE_SLOTS || slots.contains_key(&(index as u16)) => {
+                slots.insert(index as u16, slot);
+            }
+            Page::Sparse(slots) => {
+                let mut dense = vec![Slot::default(); PAGE_SIZE].into_boxed_slice();
+                for (index, slot) in slots.iter() {
+                    dense[*index as usize] = *slot;
+                }
+                dense[index] = slot;
+                *self = Page::Dense { slots: dense, used: slots.len() + 1 };
+            }
+            Page::Dense { slots, used } => {
+                if slots[index].state == 0 {
+                    *used += 1;
+                }
+                slots[index] = slot;
+            }
+        }
+    }
+
+    fn remove(&mut self, index: usize) {
+        match self {
+            Page::Sparse(slots) => {
+                slots.remove(&(index as u16));
+            }
+            Page::Dense { slots, used } => {
+                if slots[index].state != 0 {
+                    slots[index] = Slot::default();
+                    *used -= 1;
+                }
+                if *used < DENSE_SLOTS / 2 {
+                    let sparse = slots
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, slot)| slot.state != 0)
+                        .map(|(index, slot)| (index as u16, *slot))
+                        .collect();
+                    *self = Page::Sparse(sparse);
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Page::Sparse(slots) => slots.is_empty(),
+            Page::Dense { used, .. } => *used == 0,
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (usize, &Slot)> + '_> {
+        match self {
+            Page::Sparse(slots) => Box::new(slots.iter().map(|(index, slot)| (*index as usize, slot))),
+            Page::Dense { slots, .. } => Box::new(slots.iter().enumerate()),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Page::Sparse(slots) => slots.len() * mem::size_of::<(u16, Slot)>(),
+            Page::Dense { slots, .. } => slots.len() * mem::size_of::<Slot>(),
+        }
+    }
+}
+
+/// The evicted tx ids of the `PAGE_SIZE` consecutive tx ids of a page, one
+/// bit per tx id, and the client of every evicted id, in the order of the
+/// ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EvictedIds {
+    bits: Box<[u64; EVICTED_WORDS]>,
+    clients: Vec<u16>,
+}
+
+impl EvictedIds {
+    fn new() -> EvictedIds {
+        EvictedIds { bits: Box::new([0; EVICTED_WORDS]), clients: Vec::new() }
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.bits[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Number of evicted ids before `index`, the position of its client.
+    fn rank(&self, index: usize) -> usize {
+        let before: u32 = self.bits[..index / 64].iter().map(|bits| bits.count_ones()).sum();
+        let mask = (1u64 << (index % 64)) - 1;
+        (before + (self.bits[index / 64] & mask).count_ones()) as usize
+    }
+
+    fn contains(&self, index: usize, client: u16) -> bool {
+        self.is_set(index) && self.clients[self.rank(index)] == client
+    }
+
+    /// Adds the id, unless the transaction of another client with that id
+    /// was evicted before.
+    fn insert(&mut self, index: usize, client: u16) -> bool {
+        if self.is_set(index) {
+            return false;
+        }
+        let rank = self.rank(index);
+        self.clients.insert(rank, client);
+        self.bits[index / 64] |= 1 << (index % 64);
+        true
+    }
+
+    fn remove(&mut self, index: usize, client: u16) -> bool {
+        if !self.contains(index, client) {
+            return false;
+        }
+        self.clients.remove(self.rank(index));
+        self.bits[index / 64] &= !(1 << (index % 64));
+        true
+    }
+
+    fn bytes(&self) -> usize {
+        mem::size_of::<[u64; EVICTED_WORDS]>() + self.clients.capacity() * mem::size_of::<u16>()
+    }
+}
+
+/// Transactions that can still be disputed, kept in pages of `PAGE_SIZE`
+/// slots indexed by tx id. Pages are only allocated for the tx ids in use,
+/// and hold a slot per tx id once enough of them are, see `Page`. Fees and
+/// transfer recipients are rarer and are kept aside, by tx id and client.
+///
+/// A slot belongs to the client that stored a transaction in it while it
+/// was free. The transactions of other clients reusing that tx id are kept
+/// aside too, so that they never replace it or its dispute state.
+///
+/// An evicted transaction leaves its slot, only its id is kept, in a bitset
+/// per page, see `EvictedIds`. A page without used slots is dropped and
+/// reused for the next page that is needed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxStore {
+    index: Vec<u32>,
+    pages: Vec<Page>,
+    free_pages: Vec<u32>,
+    shared: HashMap<(u32, u16), Slot>,
+    fees: HashMap<(u32, u16), Decimal>,
+    recipients: HashMap<(u32, u16), u16>,
+    evicted_ids: HashMap<u32, EvictedIds>,
+    evicted_shared: HashSet<(u32, u16)>,
+    stored: usize,
+    disputes: usize,
+    evicted: usize,
+}
+
+fn split(tx: &TxId) -> (usize, usize) {
+    let tx = tx.value() as usize;
+    (tx >> PAGE_BITS, tx & (PAGE_SIZE - 1))
+}
+
+impl TxStore {
+    pub fn new() -> TxStore {
+        TxStore::default()
+    }
+
+    fn page_slot(&self, tx: &TxId) -> Option<&Slot> {
+        let (page, index) = split(tx);
+        let page = *self.index.get(page)?;
+        self.pages.get((page as usize).checked_sub(1)?)?.get(index).filter(|slot| slot.state != 0)
+    }
+
+    fn slot(&self, tx: &TxId, client: &ClientID) -> Option<&Slot> {
+        match self.shared.get(&(tx.value(), client.value())) {
+            Some(slot) => Some(slot),
+            None => self.page_slot(tx).filter(|slot| slot.client == client.value()),
+        }
+    }
+
+    fn slot_mut(&mut self, tx: &TxId, client: &ClientID) -> Option<&mut Slot> {
+        let key = (tx.value(), client.value());
+        if self.shared.contains_key(&key) {
+            return self.shared.get_mut(&key);
+        }
+        let (page, index) = split(tx);
+        let page = *self.index.get(page)?;
+        let slot = self.pages.get_mut((page as usize).checked_sub(1)?)?.get_mut(index)?;
+        Some(slot).filter(|slot| slot.state != 0 && slot.client == client.value())
+    }
+
+    /// Stores a deposit, withdrawal or transfer. A transaction stored again
+    /// for the same client keeps its dispute state.
+    pub fn insert(&mut self, tx: &TxId, client: &ClientID, transaction: &Transaction) {
+        let (kind, amount, fee, recipient) = match transaction {
+            Transaction::Deposit { amount, fee } => (KIND_DEPOSIT, *amount, *fee, None),
+            Transaction::Withdrawal { amount, fee } => (KIND_WITHDRAWAL, *amount, *fee, None),
+            Transaction::Transfer { to_client, amount } => (KIND_TRANSFER, *amount, Decimal::ZERO, Some(to_client.value())),
+            _ => return,
+        };
+        let dispute = match self.slot(tx, client).map(Slot::dispute) {
+            Some(dispute) => {
+                self.stored -= 1;
+                dispute
+            }
+            None => {
+                if self.remove_evicted(tx, client) {
+                    self.evicted -= 1;
+                }
+                0
+            }
+        };
+        let slot = Slot { amount, client: client.value(), state: kind | (dispute << DISPUTE_SHIFT) };
+        let key = (tx.value(), client.value());
+        let is_shared = self.shared.contains_key(&key) || self.page_slot(tx).is_some_and(|slot| slot.client != client.value());
+        match is_shared {
+            true => {
+                self.shared.insert(key, slot);
+            }
+            false => {
+                let (page, index) = split(tx);
+                if self.index.len() <= page {
+                    self.index.resize(page + 1, 0);
+                }
+                if self.index[page] == 0 {
+                    self.index[page] = self.free_pages.pop().unwrap_or_else(|| {
+                        self.pages.push(Page::Sparse(BTreeMap::new()));
+                        self.pages.len() as u32
+                    });
+                }
+                self.pages[self.index[page] as usize - 1].set(index, slot);
+            }
+        }
+        self.stored += 1;
+        match fee.is_zero() {
+            true => self.fees.remove(&key),
+            false => self.fees.insert(key, fee),
+        };
+        match recipient {
+            Some(recipient) => self.recipients.insert(key, recipient),
+            None => self.recipients.remove(&key),
+        };
+    }
+
+    pub fn contains(&self, tx: &TxId, client: &ClientID) -> bool {
+        self.slot(tx, client).is_some_and(|slot| slot.kind() != 0)
+    }
+
+    pub fn get(&self, tx: &TxId, client: &ClientID) -> Option<Transaction> {
+        let slot = self.slot(tx, client)?;
+        let fee = || self.fees.get(&(tx.value(), client.value())).copied().unwrap_or(Decimal::ZERO);
+        match slot.kind() {
+            KIND_DEPOSIT => Some(Transaction::Deposit { amount: slot.amount, fee: fee() }),
+            KIND_WITHDRAWAL => Some(Transaction::Withdrawal { amount: slot.amount, fee: fee() }),
+            KIND_TRANSFER => Some(Transaction::Transfer {
+                to_client: ClientID::new(self.recipients.get(&(tx.value(), client.value())).copied()?),
+                amount: slot.amount,
+            }),
+            _ => None,
+        }
+    }
+
+    /// `Dispute`, `Resolve` or `Chargeback`, the last dispute step applied
+    /// to the transaction.
+    pub fn get_dispute(&self, tx: &TxId, client: &ClientID) -> Option<&'static Transaction> {
+        match self.slot(tx, client)?.dispute() {
+            1 => Some(&Transaction::Dispute),
+            2 => Some(&Transaction::Resolve),
+            3 => Some(&Transaction::Chargeback),
+            _ => None,
+        }
+    }
+
+    pub fn set_dispute(&mut self, tx: &TxId, client: &ClientID, step: &Transaction) {
+        let dispute = match step {
+            Transaction::Dispute => 1,
+            Transaction::Resolve => 2,
+            Transaction::Chargeback => 3,
+            _ => return,
+        };
+        let slot = match self.slot_mut(tx, client) {
+            Some(slot) if slot.kind() != 0 => slot,
+            _ => return,
+        };
+        let was_disputed = slot.dispute() != 0;
+        slot.state = (slot.state & !DISPUTE_MASK) | (dispute << DISPUTE_SHIFT);
+        if !was_disputed {
+            self.disputes += 1;
+        }
+    }
+
+    /// Every stored transaction with a dispute state, with that state.
+    pub fn iter_disputes(&self) -> impl Iterator<Item = (TxId, ClientID, Transaction, &'static Transaction)> + '_ {
+        let paged = self
+            .index
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| **page != 0)
+            .flat_map(|(page, slots)| {
+                self.pages[*slots as usize - 1]
+                    .iter()
+                    .filter(|(_, slot)| slot.dispute() != 0)
+                    .map(move |(index, slot)| (((page << PAGE_BITS) | index) as u32, slot.client))
+            });
+        let shared = self.shared.iter().filter(|(_, slot)| slot.dispute() != 0).map(|(key, _)| *key);
+        paged.chain(shared).filter_map(move |(tx, client)| {
+            let (tx, client) = (TxId::new(tx), ClientID::new(client));
+            let transaction = self.get(&tx, &client)?;
+            let dispute = self.get_dispute(&tx, &client)?;
+            Some((tx, client, transaction, dispute))
+        })
+    }
+
+    /// Drops the transaction but remembers that it was stored.
+    pub fn evict(&mut self, tx: &TxId, client: &ClientID) {
+        let key = (tx.value(), client.value());
+        let slot = match self.shared.remove(&key) {
+            Some(slot) => {
+                self.evicted_shared.insert(key);
+                slot
+            }
+            None => match self.evict_page_slot(tx, client) {
+                Some(slot) => slot,
+                None => return,
+            },
+        };
+        if slot.dispute() != 0 {
+            self.disputes -= 1;
+        }
+        self.stored -= 1;
+        self.evicted += 1;
+        self.fees.remove(&key);
+        self.recipients.remove(&key);
+    }
+
+    /// Takes the slot of `tx` out of its page, drops the page if it has no
+    /// used slots left, and keeps the id.
+    fn evict_page_slot(&mut self, tx: &TxId, client: &ClientID) -> Option<Slot> {
+        let (page, index) = split(tx);
+        let number = *self.index.get(page).filter(|number| **number != 0)?;
+        let slots = &mut self.pages[number as usize - 1];
+        let slot = *slots.get(index).filter(|slot| slot.state != 0 && slot.client == client.value())?;
+        slots.remove(index);
+        if slots.is_empty() {
+            self.index[page] = 0;
+            self.free_pages.push(number);
+        }
+        let evicted_ids = self.evicted_ids.entry(page as u32).or_insert_with(EvictedIds::new);
+        if !evicted_ids.insert(index, client.value()) {
+            self.evicted_shared.insert((tx.value(), client.value()));
+        }
+        Some(slot)
+    }
+
+    fn remove_evicted(&mut self, tx: &TxId, client: &ClientID) -> bool {
+        let (page, index) = split(tx);
+        self.evicted_shared.remove(&(tx.value(), client.value()))
+            || self.evicted_ids.get_mut(&(page as u32)).is_some_and(|ids| ids.remove(index, client.value()))
+    }
+
+    pub fn is_evicted(&self, tx: &TxId, client: &ClientID) -> bool {
+        let (page, index) = split(tx);
+        self.evicted_shared.contains(&(tx.value(), client.value()))
+            || self.evicted_ids.get(&(page as u32)).is_some_and(|ids| ids.contains(index, client.value()))
+    }
+
+    /// Number of stored transactions.
+    pub fn len(&self) -> usize {
+        self.stored
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stored == 0
+    }
+
+    /// Number of stored transactions with a dispute state.
+    pub fn disputes(&self) -> usize {
+        self.disputes
+    }
+
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
+
+    /// Bytes of the allocated pages, of the evicted ids and of the side
+    /// tables.
+    pub fn bytes(&self) -> usize {
+        self.index.capacity() * mem::size_of::<u32>()
+            + self.pages.capacity() * mem::size_of::<Page>()
+            + self.pages.iter().map(Page::bytes).sum::<usize>()
+            + self.free_pages.capacity() * mem::size_of::<u32>()
+            + self.shared.capacity() * mem::size_of::<((u32, u16), Slot)>()
+            + self.fees.capacity() * mem::size_of::<((u32, u16), Decimal)>()
+            + self.recipients.capacity() * mem::size_of::<((u32, u16), u16)>()
+            + self.evicted_ids.capacity() * mem::size_of::<(u32, EvictedIds)>()
+            + self.evicted_ids.values().map(EvictedIds::bytes).sum::<usize>()
+            + self.evicted_shared.capacity() * mem::size_of::<(u32, u16)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transactions::{ClientID, Transaction, TxId};
+    use crate::tx_store::TxStore;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn check_tx_store() {
+        let mut store = TxStore::new();
+        let (client_1, client_2) = (ClientID::new(1), ClientID::new(2));
+        let deposit = Transaction::Deposit { amount: dec!(1.5), fee: dec!(0.1) };
+        let transfer = Transaction::Transfer { to_client: client_2.clone(), amount: dec!(2) };
+        store.insert(&TxId::new(1), &client_1, &deposit);
+        store.insert(&TxId::new(70000), &client_1, &transfer);
+        store.set_dispute(&TxId::new(1), &client_1, &Transaction::Dispute);
+
+        assert_eq!(store.get(&TxId::new(1), &client_1), Some(deposit.clone()));
+        assert_eq!(store.get(&TxId::new(70000), &client_1), Some(transfer));
+        assert_eq!(store.get(&TxId::new(1), &client_2), None);
+        assert_eq!(store.get(&TxId::new(2), &client_1), None);
+        assert_eq!(store.get_dispute(&TxId::new(1), &client_1), Some(&Transaction::Dispute));
+        assert_eq!(store.get_dispute(&TxId::new(70000), &client_1), None);
+        assert_eq!((store.len(), store.disputes(), store.evicted()), (2, 1, 0));
+        let disputes: Vec<_> = store.iter_disputes().collect();
+        assert_eq!(disputes, vec![(TxId::new(1), client_1.clone(), deposit.clone(), &Transaction::Dispute)]);
+
+        store.evict(&TxId::new(1), &client_1);
+        assert!(!store.contains(&TxId::new(1), &client_1));
+        assert!(store.is_evicted(&TxId::new(1), &client_1));
+        assert!(!store.is_evicted(&TxId::new(1), &client_2));
+        assert_eq!(store.get_dispute(&TxId::new(1), &client_1), None);
+        assert_eq!((store.len(), store.disputes(), store.evicted()), (1, 0, 1));
+    }
+
+    #[test]
+    fn check_tx_store_shared_tx_id() {
+        let mut store = TxStore::new();
+        let (client_1, client_2) = (ClientID::new(1), ClientID::new(2));
+        let deposit_1 = Transaction::Deposit { amount: dec!(5), fee: dec!(0.5) };
+        let deposit_2 = Transaction::Deposit { amount: dec!(3), fee: dec!(0) };
+        store.insert(&TxId::new(1), &client_1, &deposit_1);
+        store.set_dispute(&TxId::new(1), &client_1, &Transaction::Dispute);
+        store.insert(&TxId::new(1), &client_2, &deposit_2);
+
+        assert_eq!(store.get(&TxId::new(1), &client_1), Some(deposit_1.clone()));
+        assert_eq!(store.get(&TxId::new(1), &client_2), Some(deposit_2.clone()));
+        assert_eq!(store.get_dispute(&TxId::new(1), &client_1), Some(&Transaction::Dispute));
+        assert_eq!(store.get_dispute(&TxId::new(1), &client_2), None);
+        assert_eq!((store.len(), store.disputes()), (2, 1));
+
+        store.set_dispute(&TxId::new(1), &client_2, &Transaction::Dispute);
+        store.evict(&TxId::new(1), &client_1);
+        assert!(store.is_evicted(&TxId::new(1), &client_1));
+        assert_eq!(store.get(&TxId::new(1), &client_2), Some(deposit_2.clone()));
+        let disputes: Vec<_> = store.iter_disputes().collect();
+        assert_eq!(disputes, vec![(TxId::new(1), client_2, deposit_2, &Transaction::Dispute)]);
+        assert_eq!((store.len(), store.disputes(), store.evicted()), (1, 1, 1));
+    }
+
+    #[test]
+    fn check_tx_store_dense_page() {
+        let mut store = TxStore::new();
+        let client = ClientID::new(1);
+        let deposit = |id: u32| Transaction::Deposit { amount: Decimal::new(id as i64, 2), fee: dec!(0) };
+        for id in (0..4096).rev().step_by(2) {
+            store.insert(&TxId::new(id), &client, &deposit(id));
+        }
+        store.set_dispute(&TxId::new(4095), &client, &Transaction::Dispute);
+        store.set_dispute(&TxId::new(1), &client, &Transaction::Resolve);
+
+        assert!((1..4096).step_by(2).all(|id| store.get(&TxId::new(id), &client) == Some(deposit(id))));
+        assert!((0..4096).step_by(2).all(|id| !store.contains(&TxId::new(id), &client)));
+        assert_eq!((store.len(), store.disputes()), (2048, 2));
+        let disputes: Vec<_> = store.iter_disputes().map(|(tx, _, _, dispute)| (tx, dispute)).collect();
+        assert_eq!(disputes, vec![(TxId::new(1), &Transaction::Resolve), (TxId::new(4095), &Transaction::Dispute)]);
+    }
+
+    #[test]
+    fn check_tx_store_evicted_page() {
+        let mut store = TxStore::new();
+        let (client_1, client_2) = (ClientID::new(1), ClientID::new(2));
+        let deposit = Transaction::Deposit { amount: dec!(1), fee: dec!(0) };
+        for id in 0..4096 {
+            let client = if id % 2 == 0 { &client_1 } else { &client_2 };
+            store.insert(&TxId::new(id), client, &deposit);
+        }
+        store.insert(&TxId::new(7), &client_1, &deposit);
+        let full = store.bytes();
+
+        for id in 0..4000 {
+            let client = if id % 2 == 0 { &client_1 } else { &client_2 };
+            store.evict(&TxId::new(id), client);
+        }
+        // Fewer than half of the dense slots are used, the page keeps only them.
+        assert!(store.bytes() < full / 4);
+        assert_eq!(store.get(&TxId::new(4001), &client_2), Some(deposit.clone()));
+        for id in 4000..4096 {
+            let client = if id % 2 == 0 { &client_1 } else { &client_2 };
+            store.evict(&TxId::new(id), client);
+        }
+        store.evict(&TxId::new(7), &client_1);
+        assert_eq!((store.len(), store.evicted()), (0, 4097));
+        assert!(store.is_evicted(&TxId::new(6), &client_1));
+        assert!(store.is_evicted(&TxId::new(7), &client_2));
+        assert!(store.is_evicted(&TxId::new(7), &client_1));
+        assert!(!store.is_evicted(&TxId::new(6), &client_2));
+        // Only the ids are left: a bit per tx id and the client of each.
+        assert!(store.bytes() < full / 8);
+
+        // Storing a transaction again reuses the dropped page.
+        store.insert(&TxId::new(6), &client_1, &deposit);
+        assert!(!store.is_evicted(&TxId::new(6), &client_1));
+        assert_eq!(store.get(&TxId::new(6), &client_1), Some(deposit));
+        assert_eq!((store.len(), store.evicted(), store.pages.len()), (1, 4096, 1));
+    }
+}